@@ -0,0 +1,81 @@
+// Scripted GDB remote protocol client for the --gdb server: reads the target
+// description and registers, runs to a breakpoint, steps once and dumps the
+// memory at I. The default breakpoint is the delay loop of roms/pong.ch8,
+// reached on the first frame; tests/gdb.rs runs the same exchange on its own.
+//
+//   chip8 --gdb 127.0.0.1:1234 roms/pong.ch8
+//   cargo run --example gdb_client -- 127.0.0.1:1234 21A
+
+use std::{
+    env,
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn request(&mut self, data: &str) -> Result<String, String> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).map_err(|e| e.to_string())?;
+        let mut reply = vec![];
+        let mut in_packet = false;
+        loop {
+            let mut byte = [0];
+            self.stream
+                .read_exact(&mut byte)
+                .map_err(|e| e.to_string())?;
+            match (in_packet, byte[0]) {
+                (false, b'$') => in_packet = true,
+                (false, _) => {}
+                (true, b'#') => break,
+                (true, byte) => reply.push(byte),
+            }
+        }
+        // Checksum of the reply, then acknowledge it
+        let mut checksum = [0; 2];
+        self.stream
+            .read_exact(&mut checksum)
+            .map_err(|e| e.to_string())?;
+        self.stream.write_all(b"+").map_err(|e| e.to_string())?;
+        let reply = String::from_utf8_lossy(&reply).into_owned();
+        println!("-> {:<24} <- {}", data, reply);
+        Ok(reply)
+    }
+}
+
+// V0-VF, then I and PC as little endian 16 bit values
+fn registers(reply: &str) -> Option<(Vec<u8>, u16, u16)> {
+    let bytes: Vec<u8> = (0..reply.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(reply.get(index..index + 2)?, 16).ok())
+        .collect::<Option<_>>()?;
+    let word = |index: usize| u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+    Some((bytes.get(..16)?.to_vec(), word(16), word(18)))
+}
+
+fn main() -> Result<(), String> {
+    let mut args = env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:1234".to_string());
+    let breakpoint = args.next().unwrap_or_else(|| "21A".to_string());
+    let stream = TcpStream::connect(&address)
+        .map_err(|e| format!("Cannot connect to {}: {}", address, e))?;
+    let mut client = Client { stream };
+
+    client.request("qSupported:swbreak+")?;
+    client.request("qXfer:features:read:target.xml:0,fff")?;
+    client.request("?")?;
+    client.request(&format!("Z0,{},2", breakpoint))?;
+    client.request("c")?;
+    let (v, i, pc) = registers(&client.request("g")?).ok_or("Invalid register reply")?;
+    println!("Stopped at {:#05X}, I={:#05X}, V={:02X?}", pc, i, v);
+    client.request(&format!("z0,{},2", breakpoint))?;
+    client.request("s")?;
+    let (_, i, pc) = registers(&client.request("g")?).ok_or("Invalid register reply")?;
+    println!("Stepped to {:#05X}", pc);
+    client.request(&format!("m{:x},8", i))?;
+    client.request("D")?;
+    Ok(())
+}
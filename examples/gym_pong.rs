@@ -0,0 +1,68 @@
+// Random agent on roms/pong.ch8 through the gym style environment, then the
+// step rate of a clone running on another thread.
+//
+//   cargo run --release --example gym_pong -- roms/pong.ch8 10000
+
+use std::{env, path::PathBuf, thread, time::Instant};
+
+use chip8::{
+    database::RomDatabase,
+    gym::{Environment, EnvironmentOptions, Game, ObservationKind},
+    rom::{Rom, RomSource},
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+// Up, down or nothing with the left paddle
+const ACTIONS: [u16; 3] = [1 << 0x1, 1 << 0x4, 0];
+
+fn run(mut environment: Environment, steps: u64, seed: u64) -> f32 {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut total = 0.0;
+    environment.reset();
+    for _ in 0..steps {
+        let action = ACTIONS[rng.gen_range(0..ACTIONS.len())];
+        let (_, reward, done) = environment.step(action);
+        total += reward;
+        if done {
+            environment.reset();
+        }
+    }
+    total
+}
+
+fn main() -> Result<(), String> {
+    let mut args = env::args().skip(1);
+    let path = PathBuf::from(args.next().unwrap_or_else(|| "roms/pong.ch8".to_string()));
+    let steps = match args.next() {
+        Some(steps) => steps
+            .parse()
+            .map_err(|_| format!("Invalid step count {}", steps))?,
+        None => 10_000,
+    };
+    let rom = Rom::read(&RomSource::File(path))?;
+    let options = EnvironmentOptions {
+        observation: ObservationKind::Display,
+        frames_per_step: 4,
+        max_steps: Some(2_000),
+        seed: 1,
+    };
+    let environment = Environment::new(&rom, &RomDatabase::bundled(), options, Game::pong())?;
+    println!("Observations of {} bytes", environment.observation_size());
+
+    let start = Instant::now();
+    let total = run(environment.clone(), steps, 1);
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{} steps, reward {}, {:.0} steps/s",
+        steps,
+        total,
+        steps as f64 / elapsed
+    );
+
+    let worker = environment.clone();
+    let total = thread::spawn(move || run(worker, steps, 2))
+        .join()
+        .map_err(|_| "Worker thread panicked".to_string())?;
+    println!("Clone on a thread: {} steps, reward {}", steps, total);
+    Ok(())
+}
@@ -0,0 +1,193 @@
+// Octo cartridges: GIF images carrying an Octo program and its options.
+//
+// Every pixel of every frame stores two bits of payload in the low bits of its
+// palette index, four pixels make a byte, most significant bits first. The
+// payload starts with its length as a 32 bit big endian integer, followed by
+// a UTF-8 JSON object: {"program": "<Octo source>", "options": {...}}.
+// The program is Octo source code, it is assembled before being loaded.
+
+use std::collections::HashMap;
+
+use gif::{ColorOutput, DecodeOptions};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::octo;
+use crate::palette::{parse_color, Palette};
+use crate::settings::Overrides;
+
+pub const GIF_MAGIC: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: HashMap<String, Value>,
+}
+
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    // Palette, tickrate and quirks from the cartridge options
+    pub settings: Overrides,
+}
+
+pub fn is_cartridge(bytes: &[u8]) -> bool {
+    GIF_MAGIC.iter().any(|magic| bytes.starts_with(magic))
+}
+
+fn payload_bytes(gif: &[u8]) -> Result<Vec<u8>, String> {
+    let invalid = |e: gif::DecodingError| format!("Invalid cartridge image: {}", e);
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(invalid)?;
+    let mut pixels = vec![];
+    while let Some(frame) = decoder.read_next_frame().map_err(invalid)? {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+    Ok(pixels
+        .chunks_exact(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .fold(0, |byte, pixel| (byte << 2) | (pixel & 0x03))
+        })
+        .collect())
+}
+
+fn settings_from_options(options: &HashMap<String, Value>) -> Result<Overrides, String> {
+    let mut settings = Overrides::default();
+    let flag = |name: &str| options.get(name).and_then(Value::as_bool);
+    let color = |name: &str| options.get(name).and_then(Value::as_str).map(parse_color);
+    if let Some(tickrate) = options.get("tickrate").and_then(Value::as_u64) {
+        settings.tickrate = Some(tickrate as u32);
+    }
+    // Octo's memory size option tells which platform the program targets
+    settings.platform = match options.get("maxSize").and_then(Value::as_u64) {
+        Some(3216) => Some("originalChip8".to_string()),
+        Some(3583) => Some("superchip".to_string()),
+        Some(65024) => Some("xochip".to_string()),
+        _ => None,
+    };
+    if let (Some(background), Some(foreground)) = (color("backgroundColor"), color("fillColor")) {
+        settings.palette = Some(Palette {
+            background: background?,
+            foreground: foreground?,
+        });
+    }
+    // Octo quirk options and the matching chip-8-database quirks
+    let quirks = [
+        ("shiftQuirks", "shift", false),
+        ("loadStoreQuirks", "memoryLeaveIUnchanged", false),
+        ("clipQuirks", "wrap", true),
+        ("vBlankQuirks", "vblank", false),
+        ("jumpQuirks", "jump", false),
+        ("logicQuirks", "logic", false),
+    ];
+    for (option, quirk, inverted) in quirks {
+        if let Some(value) = flag(option) {
+            settings.quirks.insert(quirk.to_string(), value != inverted);
+        }
+    }
+    Ok(settings)
+}
+
+impl Cartridge {
+    pub fn decode(gif: &[u8]) -> Result<Self, String> {
+        let bytes = payload_bytes(gif)?;
+        let Some((size, rest)) = bytes.split_first_chunk::<4>() else {
+            return Err("Cartridge image is too small to hold a program".to_string());
+        };
+        let json = rest
+            .get(..u32::from_be_bytes(*size) as usize)
+            .ok_or("Cartridge payload is truncated")?;
+        let payload: Payload = serde_json::from_slice(json)
+            .map_err(|e| format!("Invalid cartridge payload: {}", e))?;
+        Ok(Cartridge {
+            program: octo::assemble(&payload.program)?,
+            settings: settings_from_options(&payload.options)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use gif::{Encoder, Frame};
+
+    use super::*;
+
+    const WIDTH: u16 = 32;
+
+    // Two bits per pixel, in frames of 4 rows
+    fn image(bytes: &[u8]) -> Vec<u8> {
+        let mut pixels: Vec<u8> = bytes
+            .iter()
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| (byte >> shift) & 0x03))
+            .collect();
+        let frame_size = WIDTH as usize * 4;
+        pixels.resize(pixels.len().div_ceil(frame_size) * frame_size, 0);
+        let mut gif = vec![];
+        let mut encoder = Encoder::new(&mut gif, WIDTH, 4, &[0; 12]).unwrap();
+        for frame in pixels.chunks(frame_size) {
+            let frame = Frame::from_indexed_pixels(WIDTH, 4, frame.to_vec(), None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        drop(encoder);
+        gif
+    }
+
+    fn encode(payload: &str) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+        image(&bytes)
+    }
+
+    #[test]
+    fn decodes_program_and_options() {
+        let payload = serde_json::json!({
+            "program": ": main\n  v0 := 5\n  jump main\n",
+            "options": {
+                "tickrate": 20,
+                "maxSize": 3216,
+                "backgroundColor": "#000000",
+                "fillColor": "#FF6600",
+                "shiftQuirks": true,
+                "clipQuirks": true,
+            },
+        });
+        // Longer than the 32 bytes of a frame, so spread over several
+        let gif = encode(&payload.to_string());
+        assert!(is_cartridge(&gif));
+
+        let cartridge = Cartridge::decode(&gif).unwrap();
+        assert_eq!(cartridge.program, [0x12, 0x02, 0x60, 0x05, 0x12, 0x02]);
+        assert_eq!(
+            cartridge.settings,
+            Overrides {
+                platform: Some("originalChip8".to_string()),
+                tickrate: Some(20),
+                palette: Some(Palette {
+                    background: [0x00, 0x00, 0x00],
+                    foreground: [0xFF, 0x66, 0x00],
+                }),
+                quirks: BTreeMap::from([("shift".to_string(), true), ("wrap".to_string(), false)]),
+                ..Overrides::default()
+            }
+        );
+    }
+
+    #[test]
+    fn broken_payloads_are_errors() {
+        // A length past the end of the image
+        let mut bytes = 256u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(br#"{"program": ""}"#);
+        assert_eq!(
+            Cartridge::decode(&image(&bytes)).err(),
+            Some("Cartridge payload is truncated".to_string())
+        );
+        assert!(Cartridge::decode(&encode("not json")).is_err());
+        assert!(Cartridge::decode(&encode(r#"{"program": "v0 := 256"}"#)).is_err());
+        assert!(Cartridge::decode(b"GIF89a").is_err());
+    }
+}
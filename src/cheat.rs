@@ -0,0 +1,449 @@
+// Cheat finder and frozen addresses, saved per ROM in
+// <config dir>/chip8/cheats.toml:
+//
+// ["607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee"]
+// file = "pong.ch8"
+//
+// [["607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee".cheats]]
+// address = 754  # 0x2F2, TOML numbers are decimal
+// value = 9
+// name = "score"
+//
+// Saved cheats are frozen every time the ROM runs in a window, the terminal or
+// with --headless. --gdb, --remote and the gym environment run the ROM as it
+// is, so what they see matches the ROM alone. With --cheat-console the
+// search and the list are driven by commands typed on stdin while the ROM
+// runs, numbers in hex:
+//
+//   search                     every address is a candidate, memory snapshot
+//   equal [value]              keep candidates equal to value, or unchanged
+//   changed|increased|decreased  compared with the previous snapshot
+//   list                       candidates and their values
+//   freeze <address> [value] [name]  rewrite the address every frame
+//   unfreeze <address>
+//   cheats                     frozen addresses
+//   save                       keep the cheats for the next runs of this ROM
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::vm::VM;
+
+const STORE_FILE: &str = "cheats.toml";
+// Candidates printed by list
+const LIST_LIMIT: usize = 32;
+
+const HELP: &str = "Cheat console commands, numbers in hex:
+  search                        start a search, every address is a candidate
+  equal [value]                 keep candidates equal to value, or unchanged
+  changed, increased, decreased keep candidates that did so since the last step
+  list                          show the candidates
+  freeze <address> [value] [name]  keep an address at a value, default current
+  unfreeze <address>            stop freezing an address
+  cheats                        show the frozen addresses
+  save                          save the cheats for this ROM";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct StoredCheats {
+    // Last file name seen for this hash, to keep the store readable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(default)]
+    cheats: Vec<Cheat>,
+}
+
+pub struct CheatStore {
+    path: PathBuf,
+    entries: BTreeMap<String, StoredCheats>,
+}
+
+impl CheatStore {
+    pub fn default_path() -> Result<PathBuf, String> {
+        dirs::config_dir()
+            .map(|directory| directory.join("chip8").join(STORE_FILE))
+            .ok_or_else(|| "Cannot find the user config directory".to_string())
+    }
+
+    // A missing file is an empty store
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid cheats file {}: {}", path.display(), e))?,
+            Err(_) if !path.exists() => BTreeMap::new(),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+        };
+        Ok(CheatStore {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn cheats(&self, sha1: &str) -> &[Cheat] {
+        self.entries
+            .get(sha1)
+            .map(|stored| stored.cheats.as_slice())
+            .unwrap_or_default()
+    }
+
+    // Replaces the list of the ROM, an empty list removes it
+    pub fn update(&mut self, sha1: &str, file: &str, cheats: &[Cheat]) -> Result<(), String> {
+        if cheats.is_empty() {
+            self.entries.remove(sha1);
+        } else {
+            self.entries.insert(
+                sha1.to_string(),
+                StoredCheats {
+                    file: Some(file.to_string()),
+                    cheats: cheats.to_vec(),
+                },
+            );
+        }
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)
+                .map_err(|e| format!("Cannot create {}: {}", directory.display(), e))?;
+        }
+        let content = toml::to_string(&self.entries)
+            .map_err(|e| format!("Cannot serialize cheats: {}", e))?;
+        fs::write(&self.path, content)
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    // Equal to a value, or to the previous snapshot without one
+    Equal(Option<u8>),
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn keeps(self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Equal(Some(value)) => current == value,
+            Comparison::Equal(None) => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+        }
+    }
+}
+
+// Candidate addresses narrowed down step by step
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    pub fn new(memory: &[u8]) -> Self {
+        CheatSearch {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    // Keeps the candidates passing the comparison and takes a new snapshot
+    pub fn narrow(&mut self, memory: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| {
+            let address = address as usize;
+            comparison.keeps(snapshot[address], memory[address])
+        });
+        self.snapshot = memory.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+// Frozen addresses and the search of the running ROM
+pub struct Cheats {
+    store: Option<PathBuf>,
+    sha1: String,
+    file: String,
+    frozen: Vec<Cheat>,
+    search: Option<CheatSearch>,
+}
+
+fn parse_hex<T: TryFrom<u32>>(value: &str, what: &str) -> Result<T, String> {
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid {} {}", what, value))
+}
+
+impl Cheats {
+    // The saved cheats of a ROM. Without a store path nothing is loaded and
+    // save fails.
+    pub fn load(store: Option<&Path>, sha1: &str, file: &str) -> Result<Self, String> {
+        let frozen = match store {
+            Some(path) => CheatStore::open(path)?.cheats(sha1).to_vec(),
+            None => vec![],
+        };
+        Ok(Cheats {
+            store: store.map(Path::to_path_buf),
+            sha1: sha1.to_string(),
+            file: file.to_string(),
+            frozen,
+            search: None,
+        })
+    }
+
+    pub fn frozen(&self) -> &[Cheat] {
+        &self.frozen
+    }
+
+    // Writes the frozen values, called before every frame
+    pub fn apply(&self, vm: &mut VM) {
+        // Addresses from a hand edited file may be out of range
+        for cheat in &self.frozen {
+            if (cheat.address as usize) < vm.memory().len() {
+                vm.set_byte(cheat.address as usize, cheat.value);
+            }
+        }
+    }
+
+    pub fn freeze(&mut self, cheat: Cheat) {
+        self.frozen.retain(|frozen| frozen.address != cheat.address);
+        self.frozen.push(cheat);
+    }
+
+    // Returns false if the address was not frozen
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        let count = self.frozen.len();
+        self.frozen.retain(|frozen| frozen.address != address);
+        self.frozen.len() != count
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = self
+            .store
+            .as_ref()
+            .ok_or("There is no config directory to save cheats in")?;
+        CheatStore::open(path)?.update(&self.sha1, &self.file, &self.frozen)
+    }
+
+    fn narrow(&mut self, vm: &VM, comparison: Comparison) -> Result<String, String> {
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("No search, start one with search")?;
+        search.narrow(vm.memory(), comparison);
+        Ok(format!("{} candidates", search.candidates().len()))
+    }
+
+    // Runs a console command and returns what to print
+    pub fn command(&mut self, line: &str, vm: &mut VM) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |index: usize, what: &str| -> Result<Option<u32>, String> {
+            words
+                .get(index)
+                .map(|word| parse_hex(word, what))
+                .transpose()
+        };
+        match words.as_slice() {
+            [] => Ok(String::new()),
+            ["help"] => Ok(HELP.to_string()),
+            ["search"] => {
+                let search = CheatSearch::new(vm.memory());
+                let count = search.candidates().len();
+                self.search = Some(search);
+                Ok(format!("{} candidates", count))
+            }
+            ["equal"] | ["equal", _] => {
+                let value = number(1, "value")?
+                    .map(|value| {
+                        u8::try_from(value).map_err(|_| format!("Invalid value {:X}", value))
+                    })
+                    .transpose()?;
+                self.narrow(vm, Comparison::Equal(value))
+            }
+            ["changed"] => self.narrow(vm, Comparison::Changed),
+            ["increased"] => self.narrow(vm, Comparison::Increased),
+            ["decreased"] => self.narrow(vm, Comparison::Decreased),
+            ["list"] => {
+                let search = self
+                    .search
+                    .as_ref()
+                    .ok_or("No search, start one with search")?;
+                let candidates = search.candidates();
+                if candidates.is_empty() {
+                    return Ok("No candidates".to_string());
+                }
+                let mut lines: Vec<String> = candidates
+                    .iter()
+                    .take(LIST_LIMIT)
+                    .map(|&address| {
+                        format!("{:03X} = {:02X}", address, vm.memory()[address as usize])
+                    })
+                    .collect();
+                if candidates.len() > LIST_LIMIT {
+                    lines.push(format!("... {} more", candidates.len() - LIST_LIMIT));
+                }
+                Ok(lines.join("\n"))
+            }
+            ["freeze", address, ..] => {
+                let address: u16 = parse_hex(address, "address")?;
+                let current = vm
+                    .memory()
+                    .get(address as usize)
+                    .copied()
+                    .ok_or_else(|| format!("Invalid address {:X}", address))?;
+                let value = match words.get(2) {
+                    Some(value) => parse_hex(value, "value")?,
+                    None => current,
+                };
+                let name = (words.len() > 3).then(|| words[3..].join(" "));
+                self.freeze(Cheat {
+                    address,
+                    value,
+                    name,
+                });
+                self.apply(vm);
+                Ok(format!("Freezing {:03X} at {:02X}", address, value))
+            }
+            ["unfreeze", address] => {
+                let address = parse_hex(address, "address")?;
+                match self.unfreeze(address) {
+                    true => Ok(format!("Unfroze {:03X}", address)),
+                    false => Err(format!("{:03X} is not frozen", address)),
+                }
+            }
+            ["cheats"] if self.frozen.is_empty() => Ok("No cheats".to_string()),
+            ["cheats"] => Ok(self
+                .frozen
+                .iter()
+                .map(|cheat| {
+                    let line = format!("{:03X} = {:02X}", cheat.address, cheat.value);
+                    match &cheat.name {
+                        Some(name) => format!("{} {}", line, name),
+                        None => line,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ["save"] => {
+                self.save()?;
+                Ok(format!(
+                    "Saved {} cheats for {}",
+                    self.frozen.len(),
+                    self.file
+                ))
+            }
+            _ => Err(format!("Unknown command {}, try help", line.trim())),
+        }
+    }
+}
+
+// Lines typed on stdin, read by one thread for the whole process so ROMs
+// started from the launcher share it
+pub fn console_line() -> Option<String> {
+    static CONSOLE: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    let console = CONSOLE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    });
+    console.lock().ok()?.try_recv().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparisons() {
+        assert!(Comparison::Equal(Some(3)).keeps(1, 3));
+        assert!(!Comparison::Equal(Some(3)).keeps(3, 1));
+        assert!(Comparison::Equal(None).keeps(2, 2));
+        assert!(Comparison::Changed.keeps(2, 1));
+        assert!(!Comparison::Changed.keeps(2, 2));
+        assert!(Comparison::Increased.keeps(1, 2));
+        assert!(!Comparison::Increased.keeps(2, 2));
+        assert!(Comparison::Decreased.keeps(2, 1));
+        assert!(!Comparison::Decreased.keeps(1, 2));
+    }
+
+    #[test]
+    fn search_narrows_and_snapshots() {
+        let mut search = CheatSearch::new(&[5, 5, 5, 5]);
+        search.narrow(&[5, 6, 4, 6], Comparison::Changed);
+        assert_eq!(search.candidates(), [1, 2, 3]);
+        // Compared with the memory of the last step, not the first
+        search.narrow(&[5, 7, 4, 6], Comparison::Increased);
+        assert_eq!(search.candidates(), [1]);
+        search.narrow(&[5, 7, 4, 6], Comparison::Equal(Some(8)));
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn hex_numbers() {
+        assert_eq!(parse_hex::<u16>("2F2", "address"), Ok(0x2F2));
+        assert_eq!(parse_hex::<u16>("0x2f2", "address"), Ok(0x2F2));
+        assert_eq!(parse_hex::<u8>("FF", "value"), Ok(0xFF));
+        assert_eq!(
+            parse_hex::<u8>("100", "value"),
+            Err("Invalid value 100".to_string())
+        );
+        assert!(parse_hex::<u16>("lives", "address").is_err());
+    }
+
+    #[test]
+    fn console_commands() {
+        let mut vm = VM::new();
+        let mut cheats = Cheats::load(None, "sha1", "game.ch8").unwrap();
+        let mut run = |line: &str, vm: &mut VM| cheats.command(line, vm);
+        assert!(run("changed", &mut vm).is_err());
+        assert_eq!(run("search", &mut vm), Ok("4096 candidates".to_string()));
+        vm.set_byte(0x300, 3);
+        vm.set_byte(0x301, 1);
+        assert_eq!(run("increased", &mut vm), Ok("2 candidates".to_string()));
+        vm.set_byte(0x301, 0);
+        assert_eq!(run("decreased", &mut vm), Ok("1 candidates".to_string()));
+        assert_eq!(run("list", &mut vm), Ok("301 = 00".to_string()));
+        assert_eq!(
+            run("freeze 300 9 player lives", &mut vm),
+            Ok("Freezing 300 at 09".to_string())
+        );
+        assert_eq!(vm.memory()[0x300], 9);
+        assert_eq!(
+            run("cheats", &mut vm),
+            Ok("300 = 09 player lives".to_string())
+        );
+        assert!(run("freeze 1000", &mut vm).is_err());
+        // Without a store path there is nowhere to save
+        assert!(run("save", &mut vm).is_err());
+        assert_eq!(run("unfreeze 300", &mut vm), Ok("Unfroze 300".to_string()));
+        assert!(run("unfreeze 300", &mut vm).is_err());
+        assert_eq!(run("cheats", &mut vm), Ok("No cheats".to_string()));
+        assert!(run("teleport", &mut vm).is_err());
+    }
+}
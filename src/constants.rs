@@ -1,5 +1,9 @@
 pub const CHIP8_HEIGHT: usize = 32;
 pub const CHIP8_WIDTH: usize = 64;
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const PROGRAM_START: usize = 0x200;
+// Subroutine nesting levels, as in most CHIP-8 interpreters
+pub const STACK_SIZE: usize = 16;
 
 const FONTS_SIZE: usize = 80;
 
@@ -0,0 +1,190 @@
+// Code coverage: which addresses ran as instructions, were read as data
+// (DXYN sprites, FX65) or were written (FX33, FX55).
+//
+// Files ending in .info get lcov tracefile records with the address as line
+// number, anything else gets an annotated listing of the program, e.g.
+//   X--      12  0x21A  F007  LD V0, DT
+//   -R-       0  0x2EA  80    (data)
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::constants::{MEMORY_SIZE, PROGRAM_START};
+use crate::instruction::{disassemble, Access, Instruction};
+
+const EXECUTED: u8 = 0x01;
+const READ: u8 = 0x02;
+const WRITTEN: u8 = 0x04;
+
+pub struct Coverage {
+    path: PathBuf,
+    rom_name: String,
+    rom: Vec<u8>,
+    executions: Vec<u64>,
+    access: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new(path: &Path, rom_name: &str, rom: &[u8]) -> Self {
+        Coverage {
+            path: path.to_path_buf(),
+            rom_name: rom_name.to_string(),
+            rom: rom.to_vec(),
+            executions: vec![0; MEMORY_SIZE],
+            access: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    fn mark(&mut self, addresses: &[u16], flag: u8) {
+        for &address in addresses {
+            self.access[address as usize % MEMORY_SIZE] |= flag;
+        }
+    }
+
+    // Called by the VM after each executed instruction with the PC and I from
+    // before it
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16, i: u16) {
+        self.executions[pc as usize % MEMORY_SIZE] += 1;
+        self.mark(&[pc, pc + 1], EXECUTED);
+        if let Some(accesses) =
+            Instruction::from_u16(opcode).and_then(|instruction| instruction.memory_accesses(i))
+        {
+            let flag = match accesses.kind {
+                Access::Read => READ,
+                Access::Write => WRITTEN,
+            };
+            self.mark(accesses.addresses(), flag);
+        }
+    }
+
+    // Executed instructions, and the instructions in the ROM that never ran,
+    // as (address, executions). Data the ROM reads or writes does not count.
+    fn instructions(&self) -> Vec<(u16, u64)> {
+        let mut instructions = vec![];
+        let mut address = PROGRAM_START;
+        while address < self.end() {
+            let runs = self.executions[address];
+            // Nothing at 0xFFF is a whole instruction
+            let untouched = self.access[address] == 0 && self.access.get(address + 1) == Some(&0);
+            if runs > 0 || (untouched && Instruction::from_u16(self.opcode(address)).is_some()) {
+                instructions.push((address as u16, runs));
+                address += 2;
+            } else {
+                address += 1;
+            }
+        }
+        instructions
+    }
+
+    // Past the ROM and everything it touched
+    fn end(&self) -> usize {
+        let touched = self
+            .access
+            .iter()
+            .rposition(|&flags| flags != 0)
+            .map_or(0, |address| address + 1);
+        (PROGRAM_START + self.rom.len())
+            .max(touched)
+            .min(MEMORY_SIZE)
+    }
+
+    // Zero past the end of the ROM, like the VM memory
+    fn byte(&self, address: usize) -> u8 {
+        address
+            .checked_sub(PROGRAM_START)
+            .and_then(|offset| self.rom.get(offset))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn opcode(&self, address: usize) -> u16 {
+        u16::from_be_bytes([self.byte(address), self.byte(address + 1)])
+    }
+
+    fn flags(&self, address: usize) -> String {
+        let flags = self.access[address];
+        [(EXECUTED, 'X'), (READ, 'R'), (WRITTEN, 'W')]
+            .iter()
+            .map(|&(flag, letter)| if flags & flag != 0 { letter } else { '-' })
+            .collect()
+    }
+
+    fn listing(&self) -> String {
+        let instructions = self.instructions();
+        let executed = instructions.iter().filter(|(_, runs)| *runs > 0).count();
+        let program = PROGRAM_START..self.end();
+        let count = |flag: u8| {
+            program
+                .clone()
+                .filter(|&address| self.access[address] & flag != 0)
+                .count()
+        };
+        let mut listing = format!(
+            "; {}: executed {} of {} instructions ({:.1}%), {} bytes read as data, {} bytes written\n",
+            self.rom_name,
+            executed,
+            instructions.len(),
+            executed as f64 * 100.0 / instructions.len().max(1) as f64,
+            count(READ),
+            count(WRITTEN)
+        );
+        let mut instructions = instructions.into_iter().peekable();
+        let mut address = PROGRAM_START;
+        while address < self.end() {
+            let line = match instructions.next_if(|&(start, _)| start as usize == address) {
+                Some((_, runs)) => {
+                    let opcode = self.opcode(address);
+                    let line = format!(
+                        "{}{:>8}  {:#05X}  {:04X}  {}",
+                        self.flags(address),
+                        runs,
+                        address,
+                        opcode,
+                        disassemble(opcode)
+                    );
+                    address += 2;
+                    line
+                }
+                None => {
+                    let line = format!(
+                        "{}{:>8}  {:#05X}  {:02X}    (data)",
+                        self.flags(address),
+                        0,
+                        address,
+                        self.byte(address)
+                    );
+                    address += 1;
+                    line
+                }
+            };
+            listing += &line;
+            listing.push('\n');
+        }
+        listing
+    }
+
+    fn lcov(&self) -> String {
+        let instructions = self.instructions();
+        let mut lcov = format!("TN:\nSF:{}\n", self.rom_name);
+        for &(address, runs) in &instructions {
+            lcov += &format!("DA:{},{}\n", address, runs);
+        }
+        let hit = instructions.iter().filter(|(_, runs)| *runs > 0).count();
+        lcov += &format!("LH:{}\nLF:{}\nend_of_record\n", hit, instructions.len());
+        lcov
+    }
+
+    // Returns a line saying where the coverage went
+    pub fn finish(self) -> Result<String, String> {
+        let is_lcov = self
+            .path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("info"));
+        let text = if is_lcov { self.lcov() } else { self.listing() };
+        fs::write(&self.path, text)
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        Ok(format!("Saved coverage to {}", self.path.display()))
+    }
+}
@@ -0,0 +1,139 @@
+// ROM metadata keyed by SHA-1, in the chip-8-database format
+// (https://github.com/chip-8/chip-8-database): platforms.json, programs.json
+// and sha1-hashes.json. A small subset is bundled, a full copy can be loaded
+// from a directory instead.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::quirks::Quirks;
+
+const BUNDLED_PLATFORMS: &str = include_str!("../database/platforms.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../database/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../database/sha1-hashes.json");
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformProfile {
+    pub id: String,
+    pub name: String,
+    pub default_tickrate: u32,
+    pub quirks: Quirks,
+}
+
+#[derive(Deserialize, Default)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomRecord {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    colors: Colors,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, RomRecord>,
+}
+
+// What the database knows about one ROM file
+#[derive(Clone, Debug)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform_id: String,
+    pub quirks: Quirks,
+    pub tickrate: u32,
+    // Action names ("up", "a", "player2Down"...) to keypad indices
+    pub keys: HashMap<String, u8>,
+    // "#RRGGBB" colours, index 0 is the background
+    pub colors: Vec<String>,
+}
+
+pub struct RomDatabase {
+    platforms: Vec<PlatformProfile>,
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+}
+
+fn parse<T: DeserializeOwned>(name: &str, content: &str) -> Result<T, String> {
+    serde_json::from_str(content).map_err(|e| format!("Invalid ROM database {}: {}", name, e))
+}
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    sha1_smol::Sha1::from(bytes).digest().to_string()
+}
+
+impl RomDatabase {
+    pub fn bundled() -> Self {
+        Self::from_json(BUNDLED_PLATFORMS, BUNDLED_PROGRAMS, BUNDLED_HASHES)
+            .expect("Bundled ROM database is invalid")
+    }
+
+    // Directory holding the three chip-8-database files
+    pub fn load(directory: &Path) -> Result<Self, String> {
+        let read = |name: &str| {
+            let path = directory.join(name);
+            fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+        };
+        Self::from_json(
+            &read("platforms.json")?,
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+        )
+    }
+
+    fn from_json(platforms: &str, programs: &str, hashes: &str) -> Result<Self, String> {
+        Ok(RomDatabase {
+            platforms: parse("platforms.json", platforms)?,
+            programs: parse("programs.json", programs)?,
+            hashes: parse("sha1-hashes.json", hashes)?,
+        })
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&PlatformProfile> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<RomInfo> {
+        let program = self.programs.get(*self.hashes.get(sha1)?)?;
+        let rom = program.roms.get(sha1)?;
+        // Platforms the ROM only runs on with extra quirks come after the regular ones
+        let platform_id = rom
+            .platforms
+            .first()
+            .or_else(|| rom.quirky_platforms.keys().next())?;
+        let profile = self.platform(platform_id)?;
+        let mut quirks = profile.quirks;
+        if let Some(overrides) = rom.quirky_platforms.get(platform_id) {
+            for (name, &value) in overrides {
+                // Quirks this VM doesn't implement are ignored
+                let _ = quirks.set(name, value);
+            }
+        }
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform_id: platform_id.clone(),
+            quirks,
+            tickrate: rom.tickrate.unwrap_or(profile.default_tickrate),
+            keys: rom.keys.clone(),
+            colors: rom.colors.pixels.clone(),
+        })
+    }
+}
@@ -0,0 +1,473 @@
+// GDB remote serial protocol server, to debug ROMs from GDB or any client
+// speaking the protocol, see examples/gdb_client.rs.
+//
+// The VM runs headless and waits for a client before running anything.
+// Registers are V0 to VF, I, PC, SP (stack depth, read only), DT and ST, in
+// that order, with I and PC little endian; the target description names
+// them. Memory is the 4K address space. Continue runs frames as fast as
+// possible until a software breakpoint or an interrupt (Ctrl-C) from the
+// client, and steps advance the frame cycle by cycle, so timers tick as in a
+// normal run. A VM fault stops with SIGILL and the VM stays halted.
+//
+// There is no keypad, so continue also stops when FX0A waits for a key, with
+// a note on the GDB console. "monitor key <0-F>" taps a key for it.
+
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    ops::Range,
+    time::Duration,
+};
+
+use crate::constants::MEMORY_SIZE;
+use crate::input::{Input, KeyEvent, KeyEventKind, KeyPoll};
+use crate::vm::VM;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// Register numbers after V0-VF
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+const REGISTER_COUNT: usize = 21;
+
+// Stop replies, as signal numbers
+const SIGINT: &str = "S02";
+// The VM faulted, e.g. on an unknown opcode, and stays halted
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+
+const INTERRUPT: u8 = 0x03;
+
+const KEY_WAIT: &str = "FX0A waits for a key, tap one with: monitor key <0-F>\n";
+const MONITOR_HELP: &str = "Commands: key <0-F>, taps a keypad key for FX0A\n";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// addr,length
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_number(address)?, parse_number(length)?))
+}
+
+// addr,length as a range of memory, None if it does not fit
+fn parse_memory_range(text: &str) -> Option<Range<usize>> {
+    let (address, length) = parse_range(text)?;
+    let end = address
+        .checked_add(length)
+        .filter(|&end| end <= MEMORY_SIZE)?;
+    Some(address..end)
+}
+
+// The packet layer: $data#checksum, acknowledged with +
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    // The next packet, None when the client hung up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let byte = match self.read_byte() {
+                Ok(byte) => byte,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            // Acks, and interrupts that arrive while the VM is already stopped
+            if byte != b'$' {
+                continue;
+            }
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(expected);
+            if !valid {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()
+    }
+
+    // True if the client sent Ctrl-C, without waiting for it
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(1) => Ok(byte[0] == INTERRUPT),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+pub struct GdbServer {
+    vm: VM,
+    input: Input,
+    breakpoints: HashSet<u16>,
+}
+
+impl GdbServer {
+    pub fn new(vm: VM) -> Self {
+        GdbServer {
+            vm,
+            input: Input::new(),
+            breakpoints: HashSet::new(),
+        }
+    }
+
+    pub fn into_vm(self) -> VM {
+        self.vm
+    }
+
+    // Serves one client on address, e.g. 127.0.0.1:1234, until it detaches,
+    // kills the target or hangs up
+    pub fn serve(&mut self, address: &str) -> Result<(), String> {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+        println!("Waiting for a GDB client on {}", address);
+        self.serve_listener(listener)
+    }
+
+    // serve on a listener bound by the caller, e.g. to port 0
+    pub fn serve_listener(&mut self, listener: TcpListener) -> Result<(), String> {
+        let (stream, client) = listener
+            .accept()
+            .map_err(|e| format!("Cannot accept a GDB client: {}", e))?;
+        println!("GDB client connected from {}", client);
+        let _ = stream.set_nodelay(true);
+        let mut connection = Connection { stream };
+        self.session(&mut connection)
+            .map_err(|e| format!("GDB connection failed: {}", e))
+    }
+
+    fn session(&mut self, connection: &mut Connection) -> io::Result<()> {
+        while let Some(packet) = connection.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => {
+                    let reply = self.resume(connection)?;
+                    self.release_keys();
+                    reply
+                }
+                Some(b's') => {
+                    self.vm.cycle(&self.input);
+                    self.release_keys();
+                    self.stop_reply()
+                }
+                Some(b'D') => {
+                    connection.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.handle(&packet),
+            };
+            connection.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    // Packets that do not run the VM
+    fn handle(&mut self, packet: &str) -> String {
+        let error = || "E01".to_string();
+        let (command, arguments) = packet.split_at(1.min(packet.len()));
+        match command {
+            "?" => self.stop_reply(),
+            "g" => hex(&self.registers()),
+            "G" => match unhex(arguments) {
+                Some(values) if values.len() == self.registers().len() => {
+                    match self.write_registers(&values) {
+                        Some(()) => "OK".to_string(),
+                        None => error(),
+                    }
+                }
+                _ => error(),
+            },
+            "p" => match parse_number(arguments).and_then(|number| self.register(number)) {
+                Some(value) => hex(&value),
+                None => error(),
+            },
+            "P" => {
+                let written = arguments.split_once('=').and_then(|(number, value)| {
+                    self.write_register(parse_number(number)?, &unhex(value)?)
+                });
+                match written {
+                    Some(()) => "OK".to_string(),
+                    None => error(),
+                }
+            }
+            "m" => match parse_memory_range(arguments) {
+                Some(range) => hex(&self.vm.memory()[range]),
+                None => error(),
+            },
+            "M" => {
+                let written = arguments.split_once(':').and_then(|(range, data)| {
+                    let range = parse_memory_range(range)?;
+                    let data = unhex(data)?;
+                    if data.len() != range.len() {
+                        return None;
+                    }
+                    for (address, value) in range.zip(data) {
+                        self.vm.set_byte(address, value);
+                    }
+                    Some(())
+                });
+                match written {
+                    Some(()) => "OK".to_string(),
+                    None => error(),
+                }
+            }
+            // Software breakpoints, Z0,addr,kind and z0,addr,kind
+            "Z" | "z" => {
+                let address = arguments
+                    .strip_prefix("0,")
+                    .and_then(|rest| parse_number(rest.split(',').next()?));
+                match address {
+                    Some(address) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(address as u16);
+                        } else {
+                            self.breakpoints.remove(&(address as u16));
+                        }
+                        "OK".to_string()
+                    }
+                    // Other breakpoint kinds are not supported
+                    None => String::new(),
+                }
+            }
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" => match arguments.strip_prefix("Rcmd,") {
+                Some(command) => self.monitor(command),
+                None => self.query(arguments),
+            },
+            // Unsupported packets get an empty reply
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return "PacketSize=4000;qXfer:features:read+;swbreak+".to_string();
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_range(range) else {
+                return "E01".to_string();
+            };
+            let chunk = TARGET_XML
+                .get(offset.min(TARGET_XML.len())..)
+                .unwrap_or_default();
+            return if chunk.len() > length {
+                format!("m{}", &chunk[..length])
+            } else {
+                format!("l{}", chunk)
+            };
+        }
+        match query {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // "monitor <command>", hex encoded. Replies with hex encoded console output
+    // or OK.
+    fn monitor(&mut self, command: &str) -> String {
+        let Some(command) = unhex(command).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return "E01".to_string();
+        };
+        let key = match command.split_whitespace().collect::<Vec<_>>()[..] {
+            ["key", key] => u8::from_str_radix(key, 16).ok().filter(|&key| key < 16),
+            _ => None,
+        };
+        let Some(key) = key else {
+            return hex(MONITOR_HELP.as_bytes());
+        };
+        // Pressed and released at once, FX0A takes both
+        let event = |kind| KeyEvent {
+            key: key as usize,
+            kind,
+            timestamp: Duration::ZERO,
+        };
+        self.input.apply(KeyPoll {
+            events: vec![event(KeyEventKind::Pressed), event(KeyEventKind::Released)],
+            ..KeyPoll::default()
+        });
+        "OK".to_string()
+    }
+
+    // A tapped key counts for one FX0A, kept until it took it
+    fn release_keys(&mut self) {
+        if !self.vm.waiting_for_key() {
+            self.input = Input::new();
+        }
+    }
+
+    // Runs until a breakpoint, an interrupt or an FX0A without a tapped key,
+    // the instruction at the current PC runs even if it has a breakpoint
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<String> {
+        let mut first = true;
+        loop {
+            let (breakpoints, input) = (&self.breakpoints, &self.input);
+            let finished = self.vm.run_frame_while(input, |vm| {
+                // The PC is past FX0A while it waits, a breakpoint there
+                // stops once the key is in
+                let waiting = vm.waiting_for_key();
+                let blocked = waiting && input.events().is_empty();
+                let proceed = vm.fault().is_none()
+                    && !blocked
+                    && (first || waiting || !breakpoints.contains(&vm.pc()));
+                first = false;
+                proceed
+            });
+            if !finished {
+                if self.vm.waiting_for_key() && self.input.events().is_empty() {
+                    connection.send(&format!("O{}", hex(KEY_WAIT.as_bytes())))?;
+                }
+                return Ok(self.stop_reply());
+            }
+            if connection.interrupted()? {
+                return Ok(SIGINT.to_string());
+            }
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.vm.fault() {
+            Some(_) => SIGILL.to_string(),
+            None => SIGTRAP.to_string(),
+        }
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        (0..REGISTER_COUNT)
+            .flat_map(|number| self.register(number).unwrap_or_default())
+            .collect()
+    }
+
+    fn register(&self, number: usize) -> Option<Vec<u8>> {
+        let (delay, sound) = self.vm.timers();
+        let value = match number {
+            0..=15 => vec![self.vm.registers()[number]],
+            REGISTER_I => self.vm.i().to_le_bytes().to_vec(),
+            REGISTER_PC => self.vm.pc().to_le_bytes().to_vec(),
+            REGISTER_SP => vec![self.vm.stack().len() as u8],
+            REGISTER_DT => vec![delay],
+            REGISTER_ST => vec![sound],
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // All or nothing, an invalid value leaves every register as it was
+    fn write_registers(&mut self, values: &[u8]) -> Option<()> {
+        let before = self.vm.save_state();
+        let mut offset = 0;
+        for number in 0..REGISTER_COUNT {
+            let size = self.register(number).map_or(0, |value| value.len());
+            if self
+                .write_register(number, &values[offset..offset + size])
+                .is_none()
+            {
+                self.vm.load_state(&before);
+                return None;
+            }
+            offset += size;
+        }
+        Some(())
+    }
+
+    fn write_register(&mut self, number: usize, value: &[u8]) -> Option<()> {
+        let (delay, sound) = self.vm.timers();
+        match (number, value) {
+            (0..=15, &[value]) => self.vm.set_register(number, value),
+            (REGISTER_I, &[low, high]) => self.vm.set_i_register(u16::from_le_bytes([low, high])),
+            // The PC has to point at a whole instruction in memory
+            (REGISTER_PC, &[low, high]) => {
+                let pc = u16::from_le_bytes([low, high]);
+                if pc as usize > MEMORY_SIZE - 2 {
+                    return None;
+                }
+                self.vm.set_pc(pc)
+            }
+            // The stack depth follows calls and returns
+            (REGISTER_SP, &[_]) => {}
+            (REGISTER_DT, &[value]) => self.vm.set_timers(value, sound),
+            (REGISTER_ST, &[value]) => self.vm.set_timers(delay, value),
+            _ => return None,
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,248 @@
+// Gym style environment for training agents, headless and without observers.
+//
+//   let options = EnvironmentOptions::default();
+//   let mut env = Environment::new(&rom, &database, options, Game::pong())?;
+//   let mut observation = env.reset();
+//   loop {
+//       let (next, reward, done) = env.step(action(&observation));
+//       ...
+//   }
+//
+// An action is a mask of the keys held for the step, bit n for key n. The
+// observation is the display, one byte per pixel row by row, or the 4K of
+// memory. Rewards come from a Game, functions of the memory before and after
+// the step. Environments clone cheaply, e.g. one per worker thread.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH, MEMORY_SIZE};
+use crate::database::{sha1_hex, RomDatabase};
+use crate::input::Input;
+use crate::quirks::Quirks;
+use crate::rom::Rom;
+use crate::settings::RomSettings;
+use crate::vm::{State, VM};
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+// Memory before and after a step to the reward of the step
+pub type Reward = Arc<dyn Fn(&[u8], &[u8]) -> f32 + Send + Sync>;
+// Memory after a step to whether the episode is over
+pub type Done = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObservationKind {
+    #[default]
+    Display,
+    Ram,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EnvironmentOptions {
+    pub observation: ObservationKind,
+    // Frames each action is held for
+    pub frames_per_step: u32,
+    // Steps after which an episode is done, whatever the Game says
+    pub max_steps: Option<u64>,
+    // CXNN seed of the first episode, the next ones count up from it
+    pub seed: u64,
+}
+
+impl Default for EnvironmentOptions {
+    fn default() -> Self {
+        EnvironmentOptions {
+            observation: ObservationKind::Display,
+            frames_per_step: 4,
+            max_steps: None,
+            seed: 0,
+        }
+    }
+}
+
+fn check_address(address: u16) -> Result<usize, String> {
+    if (address as usize) < MEMORY_SIZE {
+        Ok(address as usize)
+    } else {
+        Err(format!("Address {:#X} is outside of memory", address))
+    }
+}
+
+// Per-ROM rewards and end of episode
+#[derive(Clone)]
+pub struct Game {
+    pub reward: Reward,
+    pub done: Option<Done>,
+}
+
+impl Game {
+    // Rewards the increase of a score byte, a wrap to 0 counts as no reward
+    pub fn score(address: u16) -> Result<Self, String> {
+        let address = check_address(address)?;
+        Ok(Game {
+            reward: Arc::new(move |before, after| {
+                after[address].saturating_sub(before[address]) as f32
+            }),
+            done: None,
+        })
+    }
+
+    // Ends episodes once a byte has a value, e.g. a lives counter at 0
+    pub fn done_when(mut self, address: u16, value: u8) -> Result<Self, String> {
+        let address = check_address(address)?;
+        self.done = Some(Arc::new(move |memory| memory[address] == value));
+        Ok(self)
+    }
+
+    // roms/pong.ch8 played with the left paddle, keys 1 and 4, against the
+    // computer. The score in VE is written as BCD to 0x2F2 when it changes,
+    // the tens digit counts the left player's points and the units the
+    // computer's. Episodes end at 9 points, before the units carry over.
+    pub fn pong() -> Self {
+        Game {
+            reward: Arc::new(|before, after| {
+                let change = |address: usize| after[address] as f32 - before[address] as f32;
+                change(0x2F3) - change(0x2F4)
+            }),
+            done: Some(Arc::new(|memory| memory[0x2F3] == 9 || memory[0x2F4] == 9)),
+        }
+    }
+}
+
+pub struct Environment {
+    vm: VM,
+    quirks: Quirks,
+    tickrate: u32,
+    // Right after the ROM was loaded, reset goes back to it
+    initial: State,
+    options: EnvironmentOptions,
+    game: Game,
+    input: Input,
+    elapsed: Duration,
+    episodes: u64,
+    steps: u64,
+}
+
+fn restore(quirks: Quirks, tickrate: u32, state: &State) -> VM {
+    let mut vm = VM::new();
+    vm.configure(quirks, tickrate);
+    vm.load_state(state);
+    vm
+}
+
+impl Environment {
+    // Quirks and tickrate come from the database and the ROM's own settings
+    pub fn new(
+        rom: &Rom,
+        database: &RomDatabase,
+        options: EnvironmentOptions,
+        game: Game,
+    ) -> Result<Self, String> {
+        let settings =
+            RomSettings::resolve(database, rom.path(), sha1_hex(&rom.bytes), &rom.settings)?;
+        rom.check_size()?;
+        Self::with_quirks(
+            &rom.bytes,
+            settings.quirks,
+            settings.tickrate,
+            options,
+            game,
+        )
+    }
+
+    pub fn with_quirks(
+        bytes: &[u8],
+        quirks: Quirks,
+        tickrate: u32,
+        options: EnvironmentOptions,
+        game: Game,
+    ) -> Result<Self, String> {
+        let mut vm = VM::new();
+        vm.configure(quirks, tickrate);
+        vm.load_rom(bytes)?;
+        let initial = vm.save_state();
+        let mut environment = Environment {
+            vm,
+            quirks,
+            tickrate,
+            initial,
+            options,
+            game,
+            input: Input::new(),
+            elapsed: Duration::ZERO,
+            episodes: 0,
+            steps: 0,
+        };
+        environment.reset();
+        Ok(environment)
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Back to the freshly loaded ROM, seeded for the next episode
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.vm.load_state(&self.initial);
+        self.vm.seed(self.options.seed.wrapping_add(self.episodes));
+        self.episodes += 1;
+        self.steps = 0;
+        self.input = Input::new();
+        self.observation()
+    }
+
+    // Holds the keys of the action for frames_per_step frames
+    pub fn step(&mut self, action: u16) -> (Vec<u8>, f32, bool) {
+        let keys: [bool; 16] = std::array::from_fn(|key| action & (1 << key) != 0);
+        let before = self.vm.memory().to_vec();
+        for _ in 0..self.options.frames_per_step {
+            self.input.update(keys, self.elapsed);
+            self.vm.run_frame(&self.input);
+            self.elapsed += FRAME_DURATION;
+        }
+        self.steps += 1;
+        let after = self.vm.memory();
+        let reward = (self.game.reward)(&before, after);
+        // A faulted VM stays halted, vm().fault() tells why
+        let done = self.game.done.as_ref().is_some_and(|done| done(after))
+            || self.options.max_steps.is_some_and(|max| self.steps >= max)
+            || self.vm.fault().is_some();
+        (self.observation(), reward, done)
+    }
+
+    pub fn observation(&self) -> Vec<u8> {
+        match self.options.observation {
+            ObservationKind::Display => self.vm.display().concat(),
+            ObservationKind::Ram => self.vm.memory().to_vec(),
+        }
+    }
+
+    // Bytes in an observation
+    pub fn observation_size(&self) -> usize {
+        match self.options.observation {
+            ObservationKind::Display => CHIP8_WIDTH * CHIP8_HEIGHT,
+            ObservationKind::Ram => self.vm.memory().len(),
+        }
+    }
+}
+
+// The clone carries on from the same state, episode and step count
+impl Clone for Environment {
+    fn clone(&self) -> Self {
+        Environment {
+            vm: restore(self.quirks, self.tickrate, &self.vm.save_state()),
+            quirks: self.quirks,
+            tickrate: self.tickrate,
+            initial: self.initial.clone(),
+            options: self.options,
+            game: self.game.clone(),
+            input: self.input.clone(),
+            elapsed: self.elapsed,
+            episodes: self.episodes,
+            steps: self.steps,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Memory and display heatmaps, saved as PNG.
+//
+// The 4K of memory is a 64x64 grid, one cell per byte, lit red where it was
+// written, green where it was read as data and blue where it ran as code.
+// Activity fades by DECAY each frame, so the image shows what the ROM did
+// recently. Next to it every display pixel is coloured by how often DXYN
+// flipped it over the whole run, black through red and yellow to white.
+
+use std::path::{Path, PathBuf};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH, MEMORY_SIZE};
+use crate::instruction::{Access, Instruction};
+use crate::screenshot;
+
+// Activity left after a frame, about half after 11 frames
+const DECAY: f32 = 0.94;
+// Image pixels per memory cell and per display pixel
+const CELL: usize = 4;
+const GRID: usize = 64;
+const GAP: usize = 8;
+const BACKGROUND: [u8; 3] = [0x10, 0x10, 0x10];
+// Cells holding a non-zero byte, without activity
+const NON_ZERO: [u8; 3] = [0x30, 0x30, 0x30];
+
+const WRITE: usize = 0;
+const READ: usize = 1;
+const EXECUTE: usize = 2;
+
+pub struct Heatmap {
+    path: PathBuf,
+    // Write, read and execute activity of each byte, 1 right after an access
+    activity: Vec<[f32; 3]>,
+    draws: [[u32; CHIP8_WIDTH]; CHIP8_HEIGHT],
+}
+
+impl Heatmap {
+    pub fn new(path: &Path) -> Self {
+        Heatmap {
+            path: path.to_path_buf(),
+            activity: vec![[0.0; 3]; MEMORY_SIZE],
+            draws: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+        }
+    }
+
+    fn touch(&mut self, addresses: &[u16], kind: usize) {
+        for &address in addresses {
+            self.activity[address as usize % MEMORY_SIZE][kind] = 1.0;
+        }
+    }
+
+    // Called by the VM after each executed instruction with the PC and I from
+    // before it
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16, i: u16) {
+        self.touch(&[pc, pc + 1], EXECUTE);
+        if let Some(accesses) =
+            Instruction::from_u16(opcode).and_then(|instruction| instruction.memory_accesses(i))
+        {
+            let kind = match accesses.kind {
+                Access::Read => READ,
+                Access::Write => WRITE,
+            };
+            self.touch(accesses.addresses(), kind);
+        }
+    }
+
+    // Called by the VM after DXYN with the display from before it
+    pub(crate) fn record_draw(
+        &mut self,
+        before: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+        after: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    ) {
+        for (y, (old, new)) in before.iter().zip(after).enumerate() {
+            for x in 0..CHIP8_WIDTH {
+                if old[x] != new[x] {
+                    self.draws[y][x] += 1;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        for activity in self.activity.iter_mut().flatten() {
+            *activity *= DECAY;
+        }
+    }
+
+    fn memory_color(&self, address: usize, value: u8) -> [u8; 3] {
+        let base = if value != 0 { NON_ZERO } else { BACKGROUND };
+        let activity = self.activity[address];
+        // Writes in red, reads in green, execution in blue
+        let mut color = base;
+        for (channel, kind) in [WRITE, READ, EXECUTE].into_iter().enumerate() {
+            let lit = (activity[kind] * 255.0) as u8;
+            color[channel] = color[channel].max(lit);
+        }
+        color
+    }
+
+    fn draw_color(count: u32, most: u32) -> [u8; 3] {
+        if count == 0 {
+            return [0, 0, 0];
+        }
+        // Logarithmic, a few hot pixels should not wash out the rest
+        let heat = ((count as f32).ln_1p() / (most as f32).ln_1p()).clamp(0.0, 1.0) * 3.0;
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0) as u8;
+        [channel(heat), channel(heat - 1.0), channel(heat - 2.0)]
+    }
+
+    // Memory grid on the left, display heat on the right
+    pub fn save(&self, path: &Path, memory: &[u8]) -> Result<(), String> {
+        let width = GRID * CELL + GAP + CHIP8_WIDTH * CELL;
+        let height = GRID * CELL;
+        let mut image = vec![BACKGROUND; width * height];
+        let mut fill = |left: usize, top: usize, color: [u8; 3]| {
+            for y in top..top + CELL {
+                image[y * width + left..y * width + left + CELL].fill(color);
+            }
+        };
+        for (address, &value) in memory.iter().enumerate().take(MEMORY_SIZE) {
+            let (column, row) = (address % GRID, address / GRID);
+            fill(column * CELL, row * CELL, self.memory_color(address, value));
+        }
+        let most = self.draws.iter().flatten().copied().max().unwrap_or(0);
+        let left = GRID * CELL + GAP;
+        for (y, row) in self.draws.iter().enumerate() {
+            for (x, &count) in row.iter().enumerate() {
+                fill(left + x * CELL, y * CELL, Self::draw_color(count, most));
+            }
+        }
+        screenshot::write_png(path, width, height, &image.concat())
+    }
+
+    // Written to the path given when the heatmap was created, returns a line
+    // saying so
+    pub fn finish(self, memory: &[u8]) -> Result<String, String> {
+        self.save(&self.path, memory)?;
+        Ok(format!("Saved heatmap {}", self.path.display()))
+    }
+}
@@ -0,0 +1,197 @@
+// Hex keypad state with the press/release edges seen between two polls.
+// Frontends report their key events, so a key tapped between two polls still
+// counts; sources that only have states, e.g. movies, get edges by comparing
+// them.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Pressed,
+    Released,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    pub key: usize,
+    pub kind: KeyEventKind,
+    // Time since the frontend started polling
+    pub timestamp: Duration,
+}
+
+// What a frontend saw of the keypad since its last poll
+#[derive(Clone, Debug, Default)]
+pub struct KeyPoll {
+    // In the order they happened
+    pub events: Vec<KeyEvent>,
+    // Keys held at the end of the poll
+    pub keys: [bool; 16],
+    // Time of the poll, on the clock of the events
+    pub timestamp: Duration,
+}
+
+#[derive(Clone, Default)]
+pub struct Input {
+    state: [bool; 16],
+    events: Vec<KeyEvent>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces the current state with a new snapshot, previous events are dropped
+    pub fn update(&mut self, keys: [bool; 16], timestamp: Duration) {
+        self.apply(KeyPoll {
+            events: vec![],
+            keys,
+            timestamp,
+        });
+    }
+
+    // Takes the events of a poll in place of the previous ones. Events that
+    // change nothing, e.g. from a second key bound to a held keypad key, are
+    // skipped. Keys held differently than the events say, e.g. let go while
+    // the window was not focused, get events at the time of the poll.
+    pub fn apply(&mut self, poll: KeyPoll) {
+        self.events.clear();
+        for event in poll.events {
+            let down = event.kind == KeyEventKind::Pressed;
+            if self.state[event.key] != down {
+                self.state[event.key] = down;
+                self.events.push(event);
+            }
+        }
+        for (key, &down) in poll.keys.iter().enumerate() {
+            if self.state[key] != down {
+                self.events.push(KeyEvent {
+                    key,
+                    kind: match down {
+                        true => KeyEventKind::Pressed,
+                        false => KeyEventKind::Released,
+                    },
+                    timestamp: poll.timestamp,
+                });
+            }
+        }
+        self.state = poll.keys;
+    }
+
+    pub fn state(&self) -> [bool; 16] {
+        self.state
+    }
+
+    pub fn is_down(&self, key: usize) -> bool {
+        self.state[key]
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    pub fn pressed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.events_of(KeyEventKind::Pressed)
+    }
+
+    pub fn released(&self) -> impl Iterator<Item = usize> + '_ {
+        self.events_of(KeyEventKind::Released)
+    }
+
+    fn events_of(&self, kind: KeyEventKind) -> impl Iterator<Item = usize> + '_ {
+        self.events
+            .iter()
+            .filter(move |event| event.kind == kind)
+            .map(|event| event.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(held: &[usize]) -> [bool; 16] {
+        std::array::from_fn(|key| held.contains(&key))
+    }
+
+    fn event(key: usize, kind: KeyEventKind, millis: u64) -> KeyEvent {
+        KeyEvent {
+            key,
+            kind,
+            timestamp: Duration::from_millis(millis),
+        }
+    }
+
+    fn edges(input: &Input) -> Vec<(usize, KeyEventKind, u64)> {
+        input
+            .events()
+            .iter()
+            .map(|event| (event.key, event.kind, event.timestamp.as_millis() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_give_edges() {
+        let mut input = Input::new();
+        input.update(keys(&[1, 4]), Duration::from_millis(16));
+        assert_eq!(input.pressed().collect::<Vec<_>>(), [1, 4]);
+        assert!(input.is_down(4));
+        // Held keys are no new presses
+        input.update(keys(&[4]), Duration::from_millis(33));
+        assert_eq!(edges(&input), [(1, KeyEventKind::Released, 33)]);
+        input.update(keys(&[4]), Duration::from_millis(50));
+        assert!(input.events().is_empty());
+    }
+
+    #[test]
+    fn taps_between_polls_count() {
+        let mut input = Input::new();
+        input.apply(KeyPoll {
+            events: vec![
+                event(5, KeyEventKind::Pressed, 3),
+                event(5, KeyEventKind::Released, 9),
+            ],
+            keys: keys(&[]),
+            timestamp: Duration::from_millis(16),
+        });
+        assert_eq!(
+            edges(&input),
+            [
+                (5, KeyEventKind::Pressed, 3),
+                (5, KeyEventKind::Released, 9)
+            ]
+        );
+        assert!(!input.is_down(5));
+        assert_eq!(input.pressed().collect::<Vec<_>>(), [5]);
+        assert_eq!(input.released().collect::<Vec<_>>(), [5]);
+    }
+
+    #[test]
+    fn events_are_checked_against_held_keys() {
+        let mut input = Input::new();
+        input.apply(KeyPoll {
+            // A press of a key already pressed by another binding
+            events: vec![
+                event(2, KeyEventKind::Pressed, 1),
+                event(2, KeyEventKind::Pressed, 2),
+            ],
+            // Key 7 went down without an event, e.g. held by a script
+            keys: keys(&[2, 7]),
+            timestamp: Duration::from_millis(16),
+        });
+        assert_eq!(
+            edges(&input),
+            [
+                (2, KeyEventKind::Pressed, 1),
+                (7, KeyEventKind::Pressed, 16)
+            ]
+        );
+        // Released while the window was not focused, no event for it
+        input.apply(KeyPoll {
+            events: vec![],
+            keys: keys(&[7]),
+            timestamp: Duration::from_millis(33),
+        });
+        assert_eq!(edges(&input), [(2, KeyEventKind::Released, 33)]);
+    }
+}
@@ -1,46 +1,281 @@
-// Instruction module for debugging purposes
+// Instruction module for debugging purposes, decodes opcodes into
+// instructions displayed with the usual CHIP-8 assembly mnemonics
 
-#[derive(Debug)]
-enum Instruction {
-    ClearScreen,                       // 00E0 (clear screen)
-    Jump { adress: u16 },              // 1NNN (jump)
-    SetRegister { x: u8, value: u8 },  // 6XNN (set register VX)
-    AddRegister { x: u8, value: u8 },  // 7XNN (add value to register VX)
-    SetIRegister { adress: u16 },      // ANNN (set index register I)
-    Draw { x: u8, y: u8, nibble: u8 }, // DXYN (display/draw)
+use std::fmt::{self, Display};
+
+use crate::constants::MEMORY_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Addresses one instruction reads or writes, at most the 16 of FX55
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryAccesses {
+    pub kind: Access,
+    addresses: [u16; 16],
+    count: usize,
+}
+
+impl MemoryAccesses {
+    pub fn addresses(&self) -> &[u16] {
+        &self.addresses[..self.count]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    ClearScreen,                            // 00E0 (clear screen)
+    Return,                                 // 00EE (return from subroutine)
+    Jump { adress: u16 },                   // 1NNN (jump)
+    Call { adress: u16 },                   // 2NNN (call subroutine)
+    SkipEqual { x: u8, value: u8 },         // 3XNN (skip if VX == NN)
+    SkipNotEqual { x: u8, value: u8 },      // 4XNN (skip if VX != NN)
+    SkipRegistersEqual { x: u8, y: u8 },    // 5XY0 (skip if VX == VY)
+    SetRegister { x: u8, value: u8 },       // 6XNN (set register VX)
+    AddRegister { x: u8, value: u8 },       // 7XNN (add value to register VX)
+    Copy { x: u8, y: u8 },                  // 8XY0 (VX = VY)
+    Or { x: u8, y: u8 },                    // 8XY1
+    And { x: u8, y: u8 },                   // 8XY2
+    Xor { x: u8, y: u8 },                   // 8XY3
+    AddRegisters { x: u8, y: u8 },          // 8XY4 (VF = carry)
+    Subtract { x: u8, y: u8 },              // 8XY5 (VX = VX - VY, VF = no borrow)
+    ShiftRight { x: u8, y: u8 },            // 8XY6
+    SubtractReversed { x: u8, y: u8 },      // 8XY7 (VX = VY - VX, VF = no borrow)
+    ShiftLeft { x: u8, y: u8 },             // 8XYE
+    SkipRegistersNotEqual { x: u8, y: u8 }, // 9XY0 (skip if VX != VY)
+    SetIRegister { adress: u16 },           // ANNN (set index register I)
+    JumpOffset { adress: u16 },             // BNNN (jump to NNN + V0)
+    Random { x: u8, mask: u8 },             // CXNN (VX = random & NN)
+    Draw { x: u8, y: u8, nibble: u8 },      // DXYN (display/draw)
+    SkipKeyPressed { x: u8 },               // EX9E
+    SkipKeyNotPressed { x: u8 },            // EXA1
+    ReadDelay { x: u8 },                    // FX07 (VX = delay timer)
+    WaitKey { x: u8 },                      // FX0A (wait for a key press and release)
+    SetDelay { x: u8 },                     // FX15
+    SetSound { x: u8 },                     // FX18
+    AddI { x: u8 },                         // FX1E
+    FontCharacter { x: u8 },                // FX29 (I = font sprite of VX)
+    Bcd { x: u8 },                          // FX33
+    Store { x: u8 },                        // FX55 (V0..VX to memory at I)
+    Load { x: u8 },                         // FX65 (memory at I to V0..VX)
+}
+
+// Groups of instructions, used to filter traces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionClass {
+    Flow,       // jumps, calls and returns
+    Skip,       // conditional skips, including the key tests
+    Arithmetic, // register loads, logic and arithmetic, random
+    Index,      // I register
+    Display,    // clear and draw
+    Input,      // key wait
+    Timer,      // delay and sound timers
+    Memory,     // BCD, register stores and loads
+}
+
+impl InstructionClass {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        let class = match name {
+            "flow" => InstructionClass::Flow,
+            "skip" => InstructionClass::Skip,
+            "arithmetic" => InstructionClass::Arithmetic,
+            "index" => InstructionClass::Index,
+            "display" => InstructionClass::Display,
+            "input" => InstructionClass::Input,
+            "timer" => InstructionClass::Timer,
+            "memory" => InstructionClass::Memory,
+            _ => {
+                return Err(format!(
+                    "Unknown instruction class {}, use flow, skip, arithmetic, index, display, input, timer or memory",
+                    name
+                ))
+            }
+        };
+        Ok(class)
+    }
 }
+
 impl Instruction {
+    pub fn class(&self) -> InstructionClass {
+        match self {
+            Instruction::Return
+            | Instruction::Jump { .. }
+            | Instruction::Call { .. }
+            | Instruction::JumpOffset { .. } => InstructionClass::Flow,
+            Instruction::SkipEqual { .. }
+            | Instruction::SkipNotEqual { .. }
+            | Instruction::SkipRegistersEqual { .. }
+            | Instruction::SkipRegistersNotEqual { .. }
+            | Instruction::SkipKeyPressed { .. }
+            | Instruction::SkipKeyNotPressed { .. } => InstructionClass::Skip,
+            Instruction::SetRegister { .. }
+            | Instruction::AddRegister { .. }
+            | Instruction::Copy { .. }
+            | Instruction::Or { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::AddRegisters { .. }
+            | Instruction::Subtract { .. }
+            | Instruction::ShiftRight { .. }
+            | Instruction::SubtractReversed { .. }
+            | Instruction::ShiftLeft { .. }
+            | Instruction::Random { .. } => InstructionClass::Arithmetic,
+            Instruction::SetIRegister { .. }
+            | Instruction::AddI { .. }
+            | Instruction::FontCharacter { .. } => InstructionClass::Index,
+            Instruction::ClearScreen | Instruction::Draw { .. } => InstructionClass::Display,
+            Instruction::WaitKey { .. } => InstructionClass::Input,
+            Instruction::ReadDelay { .. }
+            | Instruction::SetDelay { .. }
+            | Instruction::SetSound { .. } => InstructionClass::Timer,
+            Instruction::Bcd { .. } | Instruction::Store { .. } | Instruction::Load { .. } => {
+                InstructionClass::Memory
+            }
+        }
+    }
+
+    // Memory read as data or written when the instruction runs with I, None
+    // for instructions that do neither. Sprites wrap around the 4K like the VM
+    // does, FX33, FX55 and FX65 fault rather than run past the end of memory.
+    pub fn memory_accesses(&self, i: u16) -> Option<MemoryAccesses> {
+        let (kind, count, wraps) = match *self {
+            Instruction::Draw { nibble, .. } => (Access::Read, nibble as usize, true),
+            Instruction::Load { x } => (Access::Read, x as usize + 1, false),
+            Instruction::Store { x } => (Access::Write, x as usize + 1, false),
+            Instruction::Bcd { .. } => (Access::Write, 3, false),
+            _ => return None,
+        };
+        let mut accesses = MemoryAccesses {
+            kind,
+            addresses: [0; 16],
+            count: 0,
+        };
+        for offset in 0..count {
+            let address = match i as usize + offset {
+                address if wraps => address & 0x0FFF,
+                address if address < MEMORY_SIZE => address,
+                _ => break,
+            };
+            accesses.addresses[accesses.count] = address as u16;
+            accesses.count += 1;
+        }
+        Some(accesses)
+    }
+
     pub fn from_u16(value: u16) -> Option<Self> {
         let [first_chunk, second_chunk] = value.to_be_bytes();
-        match value {
-            0x00E0 => Some(Instruction::ClearScreen),
-            _ => match first_chunk >> 4 {
-                0x01 => {
-                    let adress = u16::from_ne_bytes([second_chunk, (first_chunk & 0x0F)]);
-                    Some(Instruction::Jump { adress })
-                }
-                0x06 => {
-                    let register = first_chunk & 0x0F;
-                    let value = second_chunk;
-                    Some(Instruction::SetRegister { x: register, value })
-                }
-                0x07 => {
-                    let register = first_chunk & 0x0F;
-                    let value = second_chunk;
-                    Some(Instruction::AddRegister { x: register, value })
-                }
-                0x0A => {
-                    let adress = u16::from_ne_bytes([second_chunk, (first_chunk & 0x0F)]);
-                    Some(Instruction::SetIRegister { adress })
-                }
-                0x0D => {
-                    let x = first_chunk & 0x0F;
-                    let y = second_chunk >> 4;
-                    let nibble = second_chunk & 0x0F;
-                    Some(Instruction::Draw { x, y, nibble })
-                }
-                _ => None,
+        let x = first_chunk & 0x0F;
+        let y = second_chunk >> 4;
+        let nibble = second_chunk & 0x0F;
+        let adress = value & 0x0FFF;
+        let instruction = match value {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            _ => match (first_chunk >> 4, nibble) {
+                (0x01, _) => Instruction::Jump { adress },
+                (0x02, _) => Instruction::Call { adress },
+                (0x03, _) => Instruction::SkipEqual {
+                    x,
+                    value: second_chunk,
+                },
+                (0x04, _) => Instruction::SkipNotEqual {
+                    x,
+                    value: second_chunk,
+                },
+                (0x05, 0x00) => Instruction::SkipRegistersEqual { x, y },
+                (0x06, _) => Instruction::SetRegister {
+                    x,
+                    value: second_chunk,
+                },
+                (0x07, _) => Instruction::AddRegister {
+                    x,
+                    value: second_chunk,
+                },
+                (0x08, 0x00) => Instruction::Copy { x, y },
+                (0x08, 0x01) => Instruction::Or { x, y },
+                (0x08, 0x02) => Instruction::And { x, y },
+                (0x08, 0x03) => Instruction::Xor { x, y },
+                (0x08, 0x04) => Instruction::AddRegisters { x, y },
+                (0x08, 0x05) => Instruction::Subtract { x, y },
+                (0x08, 0x06) => Instruction::ShiftRight { x, y },
+                (0x08, 0x07) => Instruction::SubtractReversed { x, y },
+                (0x08, 0x0E) => Instruction::ShiftLeft { x, y },
+                (0x09, 0x00) => Instruction::SkipRegistersNotEqual { x, y },
+                (0x0A, _) => Instruction::SetIRegister { adress },
+                (0x0B, _) => Instruction::JumpOffset { adress },
+                (0x0C, _) => Instruction::Random {
+                    x,
+                    mask: second_chunk,
+                },
+                (0x0D, _) => Instruction::Draw { x, y, nibble },
+                _ => match (first_chunk >> 4, second_chunk) {
+                    (0x0E, 0x9E) => Instruction::SkipKeyPressed { x },
+                    (0x0E, 0xA1) => Instruction::SkipKeyNotPressed { x },
+                    (0x0F, 0x07) => Instruction::ReadDelay { x },
+                    (0x0F, 0x0A) => Instruction::WaitKey { x },
+                    (0x0F, 0x15) => Instruction::SetDelay { x },
+                    (0x0F, 0x18) => Instruction::SetSound { x },
+                    (0x0F, 0x1E) => Instruction::AddI { x },
+                    (0x0F, 0x29) => Instruction::FontCharacter { x },
+                    (0x0F, 0x33) => Instruction::Bcd { x },
+                    (0x0F, 0x55) => Instruction::Store { x },
+                    (0x0F, 0x65) => Instruction::Load { x },
+                    _ => return None,
+                },
             },
+        };
+        Some(instruction)
+    }
+}
+
+// Mnemonic of an opcode, DW for data that is not an instruction
+pub fn disassemble(opcode: u16) -> String {
+    match Instruction::from_u16(opcode) {
+        Some(instruction) => instruction.to_string(),
+        None => format!("DW {:#06X}", opcode),
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump { adress } => write!(f, "JP {:#05X}", adress),
+            Instruction::Call { adress } => write!(f, "CALL {:#05X}", adress),
+            Instruction::SkipEqual { x, value } => write!(f, "SE V{:X}, {:#04X}", x, value),
+            Instruction::SkipNotEqual { x, value } => write!(f, "SNE V{:X}, {:#04X}", x, value),
+            Instruction::SkipRegistersEqual { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SetRegister { x, value } => write!(f, "LD V{:X}, {:#04X}", x, value),
+            Instruction::AddRegister { x, value } => write!(f, "ADD V{:X}, {:#04X}", x, value),
+            Instruction::Copy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegisters { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Subtract { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubtractReversed { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipRegistersNotEqual { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIRegister { adress } => write!(f, "LD I, {:#05X}", adress),
+            Instruction::JumpOffset { adress } => write!(f, "JP V0, {:#05X}", adress),
+            Instruction::Random { x, mask } => write!(f, "RND V{:X}, {:#04X}", x, mask),
+            Instruction::Draw { x, y, nibble } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, nibble),
+            Instruction::SkipKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::ReadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::FontCharacter { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Store { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load { x } => write!(f, "LD V{:X}, [I]", x),
         }
     }
 }
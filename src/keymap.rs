@@ -0,0 +1,177 @@
+// Keyboard bindings for the hex keypad, optionally loaded from a TOML file
+//
+// mode = "scancode"            # "keycode" (default) follows the active layout,
+//                              # "scancode" uses physical key positions
+// [keys]
+// 1 = 0x1
+// Q = 0x4
+//
+// [controller]                 # game controller buttons, SDL mapping names
+// dpup = 0x2                   # (a, b, x, y, dpup, dpdown, start, back...)
+// a = 0x5
+//
+// [rom."pong.ch8"]             # per-ROM override, each table replaces
+// keys = { W = 0x1, S = 0x4, Up = 0xC, Down = 0xD }  # the global one
+// controller = { dpup = 0x1, dpdown = 0x4 }
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyMode {
+    #[default]
+    Keycode,
+    Scancode,
+}
+
+// SDL key names (see SDL_GetKeyName / SDL_GetScancodeName) and game controller
+// button names (see SDL_GameControllerGetStringForButton) mapped to keypad indices
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    pub mode: KeyMode,
+    pub keys: HashMap<String, u8>,
+    pub buttons: HashMap<String, u8>,
+}
+
+#[derive(Deserialize, Default)]
+struct RomOverride {
+    mode: Option<KeyMode>,
+    keys: Option<HashMap<String, u8>>,
+    controller: Option<HashMap<String, u8>>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct KeyMap {
+    #[serde(default)]
+    mode: KeyMode,
+    keys: Option<HashMap<String, u8>>,
+    controller: Option<HashMap<String, u8>>,
+    #[serde(default)]
+    rom: HashMap<String, RomOverride>,
+}
+
+// Original QWERTY layout:
+// 1 2 3 4      1 2 3 C
+// Q W E R  ->  4 5 6 D
+// A S D F      7 8 9 E
+// Z X C V      A 0 B F
+const DEFAULT_KEYS: [(&str, u8); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+// D-pad on the 2/4/6/8 cross most games use for movement, face buttons around it
+const DEFAULT_BUTTONS: [(&str, u8); 8] = [
+    ("dpup", 0x2),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("dpdown", 0x8),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0x7),
+    ("y", 0x9),
+];
+
+// chip-8-database key hint names and the controller button they go to
+const HINT_BUTTONS: [(&str, &str); 6] = [
+    ("up", "dpup"),
+    ("down", "dpdown"),
+    ("left", "dpleft"),
+    ("right", "dpright"),
+    ("a", "a"),
+    ("b", "b"),
+];
+
+fn to_table(defaults: &[(&str, u8)]) -> HashMap<String, u8> {
+    defaults
+        .iter()
+        .map(|&(name, index)| (name.to_string(), index))
+        .collect()
+}
+
+fn check_keys(keys: &HashMap<String, u8>) -> Result<(), String> {
+    match keys.iter().find(|(_, &index)| index > 0xF) {
+        Some((name, index)) => Err(format!(
+            "Key {} is bound to {:#04X?}, keypad indices go from 0x0 to 0xF",
+            name, index
+        )),
+        None => Ok(()),
+    }
+}
+
+impl KeyMap {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read key bindings {}: {}", path, e))?;
+        let keymap: KeyMap = toml::from_str(&content)
+            .map_err(|e| format!("Invalid key bindings {}: {}", path, e))?;
+        let rom_tables = keymap
+            .rom
+            .values()
+            .flat_map(|rom_override| [&rom_override.keys, &rom_override.controller]);
+        for table in [&keymap.keys, &keymap.controller]
+            .into_iter()
+            .chain(rom_tables)
+            .flatten()
+        {
+            check_keys(table)?;
+        }
+        Ok(keymap)
+    }
+
+    // Bindings outside of any ROM, e.g. in the launcher
+    pub fn global_bindings(&self) -> Bindings {
+        Bindings {
+            mode: self.mode,
+            keys: self.keys.clone().unwrap_or_else(|| to_table(&DEFAULT_KEYS)),
+            buttons: self
+                .controller
+                .clone()
+                .unwrap_or_else(|| to_table(&DEFAULT_BUTTONS)),
+        }
+    }
+
+    // Overrides are looked up by the ROM file name, e.g. "pong.ch8", a path works too.
+    // Key hints from the ROM database move the D-pad and face buttons, unless
+    // the user overrode the controller table for this ROM.
+    pub fn bindings_for(&self, rom_path: &str, key_hints: &HashMap<String, u8>) -> Bindings {
+        let mut bindings = self.global_bindings();
+        for (hint, button) in HINT_BUTTONS {
+            if let Some(&index) = key_hints.get(hint) {
+                bindings.buttons.insert(button.to_string(), index);
+            }
+        }
+        let rom_name = Path::new(rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(rom_override) = self.rom.get(&rom_name) {
+            if let Some(mode) = rom_override.mode {
+                bindings.mode = mode;
+            }
+            if let Some(keys) = &rom_override.keys {
+                bindings.keys = keys.clone();
+            }
+            if let Some(buttons) = &rom_override.controller {
+                bindings.buttons = buttons.clone();
+            }
+        }
+        bindings
+    }
+}
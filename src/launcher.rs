@@ -0,0 +1,208 @@
+// In-window ROM browser listing the ROMs found in a directory
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use sdl2::pixels::Color;
+
+use crate::input::Input;
+use crate::keymap::KeyMap;
+use crate::platform::Platform;
+use crate::renderer::{Command, Renderer};
+use crate::sdl::{SDLWrapper, WINDOW_HEIGHT};
+
+const TEXT_SCALE: u32 = 4;
+const MARGIN: i32 = 16;
+const VISIBLE_ENTRIES: usize = 20;
+const NAME_WIDTH: usize = 48;
+// Characters of an error that fit on a line
+const ERROR_WIDTH: usize = 76;
+
+// Keypad navigation, matches the default 2/4/6/8 movement cross
+const KEY_UP: usize = 0x2;
+const KEY_DOWN: usize = 0x8;
+const KEY_SELECT: usize = 0x5;
+
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub platform: Platform,
+}
+
+pub fn scan_roms(directory: &Path) -> Result<Vec<RomEntry>, String> {
+    let read_dir = fs::read_dir(directory)
+        .map_err(|e| format!("Cannot list ROMs in {}: {}", directory.display(), e))?;
+    let mut entries: Vec<RomEntry> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let platform = Platform::from_path(&path)?;
+            let size = entry.metadata().ok().filter(|m| m.is_file())?.len();
+            Some(RomEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path,
+                size,
+                platform,
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    Ok(entries)
+}
+
+pub struct Launcher {
+    directory: PathBuf,
+    entries: Vec<RomEntry>,
+    selected: usize,
+    // Why the last ROM could not be loaded or stopped, shown above the help
+    error: Option<String>,
+}
+
+impl Launcher {
+    pub fn new(directory: &Path) -> Result<Self, String> {
+        Ok(Launcher {
+            directory: directory.to_path_buf(),
+            entries: scan_roms(directory)?,
+            selected: 0,
+            error: None,
+        })
+    }
+
+    pub fn set_error(&mut self, error: Option<String>) {
+        self.error = error;
+    }
+
+    fn move_selection(&mut self, up: bool) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.selected = if up {
+            self.selected
+                .checked_sub(1)
+                .unwrap_or(self.entries.len() - 1)
+        } else {
+            (self.selected + 1) % self.entries.len()
+        };
+    }
+
+    fn draw(&self, renderer: &mut SDLWrapper) {
+        let line_height = SDLWrapper::text_line_height(TEXT_SCALE);
+        let white = Color::RGB(250, 250, 250);
+        let grey = Color::RGB(130, 130, 130);
+        renderer.clear_screen();
+        renderer.draw_text(
+            MARGIN,
+            MARGIN,
+            &format!("ROMS IN {}", self.directory.display()),
+            TEXT_SCALE,
+            grey,
+        );
+        if self.entries.is_empty() {
+            renderer.draw_text(
+                MARGIN,
+                MARGIN + 2 * line_height,
+                "NO .CH8, .SC8 OR .XO8 FILES FOUND",
+                TEXT_SCALE,
+                white,
+            );
+        }
+        let first = self.selected.saturating_sub(VISIBLE_ENTRIES - 1);
+        for (row, (index, entry)) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(VISIBLE_ENTRIES)
+            .enumerate()
+        {
+            let marker = if index == self.selected { '>' } else { ' ' };
+            let name: String = entry.name.chars().take(NAME_WIDTH).collect();
+            let line = format!(
+                "{} {:<width$} {:>6} B  {}",
+                marker,
+                name,
+                entry.size,
+                entry.platform.name(),
+                width = NAME_WIDTH
+            );
+            let color = if index == self.selected { white } else { grey };
+            renderer.draw_text(
+                MARGIN,
+                MARGIN + (row as i32 + 2) * line_height,
+                &line,
+                TEXT_SCALE,
+                color,
+            );
+        }
+        if let Some(error) = &self.error {
+            let error: String = error.chars().take(ERROR_WIDTH).collect();
+            renderer.draw_text(
+                MARGIN,
+                WINDOW_HEIGHT as i32 - MARGIN - 3 * line_height,
+                &error,
+                TEXT_SCALE,
+                Color::RGB(255, 90, 90),
+            );
+        }
+        renderer.draw_text(
+            MARGIN,
+            WINDOW_HEIGHT as i32 - MARGIN - line_height,
+            "UP/DOWN OR 2/8 MOVE - ENTER OR 5 START - F1 BACK HERE - ESC QUIT",
+            TEXT_SCALE,
+            grey,
+        );
+        renderer.present();
+    }
+
+    // Returns the chosen ROM, None if the user quit
+    pub fn run(
+        &mut self,
+        renderer: &mut SDLWrapper,
+        keymap: &KeyMap,
+    ) -> Result<Option<PathBuf>, String> {
+        renderer.set_bindings(&keymap.global_bindings())?;
+        let mut input = Input::new();
+        loop {
+            let (poll, commands) = renderer.handle_event();
+            input.apply(poll);
+            let mut select = false;
+            for command in commands {
+                match command {
+                    Command::Quit => return Ok(None),
+                    Command::Up => self.move_selection(true),
+                    Command::Down => self.move_selection(false),
+                    Command::Select => select = true,
+                    Command::Launcher
+                    | Command::Screenshot
+                    | Command::Record
+                    | Command::Pause
+                    | Command::FrameAdvance
+                    | Command::Step
+                    | Command::Faster
+                    | Command::Slower
+                    | Command::Overlay => {}
+                }
+            }
+            for key in input.pressed() {
+                match key {
+                    KEY_UP => self.move_selection(true),
+                    KEY_DOWN => self.move_selection(false),
+                    KEY_SELECT => select = true,
+                    _ => {}
+                }
+            }
+            if select {
+                if let Some(entry) = self.entries.get(self.selected) {
+                    return Ok(Some(entry.path.clone()));
+                }
+            }
+            self.draw(renderer);
+            thread::sleep(Duration::from_millis(16));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+pub mod cartridge;
+pub mod cheat;
+pub mod constants;
+pub mod coverage;
+pub mod database;
+pub mod gdb;
+pub mod gym;
+pub mod heatmap;
+pub mod input;
+pub mod instruction;
+pub mod keymap;
+#[cfg(feature = "sdl")]
+pub mod launcher;
+pub mod movie;
+pub mod octo;
+pub mod overlay;
+pub mod palette;
+pub mod platform;
+pub mod profile;
+pub mod quirks;
+pub mod recording;
+pub mod reference;
+pub mod remote;
+pub mod renderer;
+pub mod rom;
+pub mod screenshot;
+pub mod script;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod settings;
+pub mod speed;
+pub mod store;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+pub mod text;
+pub mod trace;
+pub mod vm;
+#[cfg(feature = "web")]
+pub mod web;
@@ -1,10 +1,368 @@
-mod constants;
-mod renderer;
-mod vm;
-use std::env;
-use vm::*;
+use chip8::{
+    cheat::CheatStore,
+    database::{sha1_hex, RomDatabase},
+    instruction::InstructionClass,
+    keymap::KeyMap,
+    launcher::Launcher,
+    palette::Palette,
+    quirks::Quirks,
+    reference::ReferenceTrace,
+    renderer::Renderer,
+    rom::{Rom, RomSource},
+    sdl::{self, SDLWrapper},
+    settings::{Overrides, RomSettings},
+    store::SettingsStore,
+    terminal::{TerminalMode, TerminalRenderer},
+    trace::{TraceConfig, TraceFilter, TraceFormat, TraceTarget},
+    vm::{RunOptions, RunOutcome, VM},
+};
+use std::{env, path::PathBuf, str::FromStr};
+
+const USAGE: &str = "Usage: chip8 [options] [rom]
+       chip8 settings <show|set|reset> <rom> [options]
+  rom can be a file, a .gz file, a zip archive holding a single ROM, an Octo
+  cartridge (.gif), or - for stdin
+  --keymap <file>           key bindings (TOML)
+  --rom-dir <dir>           directory listed by the launcher (default: roms)
+  --rom-db <dir>            chip-8-database directory replacing the bundled one
+  --platform <id>           chip-8-database platform, e.g. originalChip8, superchip
+  --quirk <name>=<on|off>   force a quirk, can be repeated
+  --tickrate <n>            instructions per frame
+  --colors <bg>,<fg>        palette, e.g. #000000,#FFFFFF
+  --key <name>=<index>      bind an SDL key name to a keypad index, can be repeated
+  --button <name>=<index>   bind a controller button to a keypad index, can be repeated
+  --screenshot-after <n>    save a PNG screenshot after frame n (F12 saves one any time)
+  --screenshot-scale <n>    screenshot pixels per display pixel (default: 1)
+  --record <file>           record every frame from the start, to an animated GIF if the
+                            file ends in .gif, raw RGB24 frames otherwise (F10 toggles a GIF)
+  --record-scale <n>        recording pixels per display pixel (default: 1)
+  --movie-record <file>     record the keypad input of every frame and the random seed to
+                            an input movie
+  --movie <file>            replay an input movie instead of the keypad, also headless
+  --frames <n>              quit after n frames
+  --headless                run without a window, as fast as possible, with the input of
+                            --movie if given
+  --terminal <half|braille> draw in the terminal with half blocks or braille, no window
+  --cheat-console           read cheat commands on stdin while the ROM runs: search memory
+                            for values that changed, increased or decreased, freeze
+                            addresses and save them for the ROM, type help for the list;
+                            saved cheats are not applied with --gdb or --remote
+  --coverage <file>         save which addresses ran, were read as data or written, as an
+                            lcov tracefile if the file ends in .info, a listing otherwise
+  --gdb <address>           run headless and wait for a GDB client on this address, e.g.
+                            127.0.0.1:1234
+  --remote <address>        run headless, driven by JSON-lines commands on a local TCP
+                            address, e.g. 127.0.0.1:7000, or unix:<path>; the ROM is optional
+  --heatmap <file>          save a PNG of recent memory reads, writes and execution and of
+                            display draw frequency when the ROM stops and with screenshots
+  --profile                 print hot spots, subroutine cycle counts and frames over budget
+                            when the ROM stops
+  --reference <file>        run headless in lockstep with a trace from another emulator
+                            (pc=, op=, v0= to vf=, i=, sp=, stack= per line, hex) and
+                            stop at the first difference
+  --script <file>           run a Rhai script with hooks on frames, addresses and memory
+                            writes, e.g. an autoplay bot or ROM assertions
+  --trace <file|->          trace every executed instruction to a file, - for stdout
+  --trace-ring <n>          keep the last n traced instructions, printed when the ROM stops
+  --trace-format <text|json> trace lines as text or JSON (default: text)
+  --trace-range <start-end> only trace instructions in this address range, e.g. 200-2FF
+  --trace-class <name>      only trace this instruction class, can be repeated: flow, skip,
+                            arithmetic, index, display, input, timer, memory
+
+Hotkeys: Escape quit, F1 launcher, F3 debug overlay, F5 pause, F6 frame
+advance, F7 single instruction, F8 faster (2x, 4x, uncapped), F9 slower (1/2x,
+1/4x), F10 record, F12 screenshot.
+
+Settings saved with `settings set` are applied every time that ROM is loaded,
+command line options still win over them. Both win over the options stored
+in Octo cartridges.";
+
+fn expect_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} expects a value\n{}", flag, USAGE))
+}
+
+fn parse_value<T: FromStr>(value: &str, flag: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, flag))
+}
+
+// `chip8 settings ...`, edits the per-ROM settings store
+fn edit_settings(
+    action: &str,
+    rom: &Rom,
+    database: &RomDatabase,
+    keymap: &KeyMap,
+    overrides: &Overrides,
+) -> Result<(), String> {
+    let sha1 = sha1_hex(&rom.bytes);
+    let mut store = SettingsStore::open(SettingsStore::default_path().ok().as_deref())?;
+    let file = &rom.name;
+    match action {
+        "show" => match store.get(&sha1) {
+            Some(stored) => println!(
+                "{}",
+                toml::to_string(stored).map_err(|e| format!("Cannot show settings: {}", e))?
+            ),
+            None => println!("No settings saved for {} ({})", file, sha1),
+        },
+        "set" => {
+            // Check them the way running the ROM would before saving anything
+            let layered =
+                overrides.layered_over(&store.overrides(&sha1).layered_over(&rom.settings));
+            let settings = RomSettings::resolve(database, rom.path(), sha1.clone(), &layered)?;
+            let mut bindings = keymap.bindings_for(file, &settings.key_hints);
+            layered.apply_bindings(&mut bindings);
+            sdl::check_bindings(&bindings)?;
+            store.update(&sha1, file, overrides)?;
+            println!("Saved settings for {} ({})", file, sha1);
+        }
+        "reset" => {
+            if store.reset(&sha1)? {
+                println!("Removed settings for {} ({})", file, sha1);
+            } else {
+                println!("No settings saved for {} ({})", file, sha1);
+            }
+        }
+        _ => return Err(format!("Unknown settings action {}\n{}", action, USAGE)),
+    }
+    Ok(())
+}
 
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    VM::run_rom(&args[1])
+    let mut args = env::args().skip(1).peekable();
+    let settings_action = if args.peek().map(String::as_str) == Some("settings") {
+        args.next();
+        Some(expect_value(&mut args, "settings")?)
+    } else {
+        None
+    };
+    let mut rom_source = None;
+    let mut rom_dir = PathBuf::from("roms");
+    let mut keymap = KeyMap::default();
+    let mut database = RomDatabase::bundled();
+    let mut overrides = Overrides::default();
+    let mut options = RunOptions::default();
+    let mut headless = false;
+    let mut reference = None;
+    let mut gdb_address = None;
+    let mut remote_address = None;
+    let mut terminal_mode = None;
+    let mut trace_target = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_filter = TraceFilter::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keymap" => keymap = KeyMap::load(&expect_value(&mut args, &arg)?)?,
+            "--rom-dir" => rom_dir = expect_value(&mut args, &arg)?.into(),
+            "--rom-db" => database = RomDatabase::load(expect_value(&mut args, &arg)?.as_ref())?,
+            "--platform" => overrides.platform = Some(expect_value(&mut args, &arg)?),
+            "--quirk" => {
+                let (name, value) = Quirks::parse_assignment(&expect_value(&mut args, &arg)?)?;
+                overrides.quirks.insert(name, value);
+            }
+            "--tickrate" => {
+                let tickrate = expect_value(&mut args, &arg)?;
+                overrides.tickrate = Some(
+                    tickrate
+                        .parse()
+                        .map_err(|_| format!("Invalid tickrate {}", tickrate))?,
+                );
+            }
+            "--colors" => {
+                overrides.palette = Some(Palette::parse(&expect_value(&mut args, &arg)?)?)
+            }
+            "--key" => {
+                let (name, index) = Overrides::parse_binding(&expect_value(&mut args, &arg)?)?;
+                overrides.keys.insert(name, index);
+            }
+            "--button" => {
+                let (name, index) = Overrides::parse_binding(&expect_value(&mut args, &arg)?)?;
+                overrides.buttons.insert(name, index);
+            }
+            "--screenshot-after" => {
+                options.screenshot_after = Some(parse_value(&expect_value(&mut args, &arg)?, &arg)?)
+            }
+            "--screenshot-scale" => {
+                options.screenshot_scale = parse_value(&expect_value(&mut args, &arg)?, &arg)?
+            }
+            "--frames" => {
+                options.frames = Some(parse_value(&expect_value(&mut args, &arg)?, &arg)?)
+            }
+            "--record" => options.record = Some(expect_value(&mut args, &arg)?.into()),
+            "--record-scale" => {
+                options.record_scale = parse_value(&expect_value(&mut args, &arg)?, &arg)?
+            }
+            "--movie" => options.movie = Some(expect_value(&mut args, &arg)?.into()),
+            "--movie-record" => options.movie_record = Some(expect_value(&mut args, &arg)?.into()),
+            "--headless" => headless = true,
+            "--terminal" => {
+                terminal_mode = Some(TerminalMode::from_name(&expect_value(&mut args, &arg)?)?)
+            }
+            "--cheat-console" => options.cheat_console = true,
+            "--coverage" => options.coverage = Some(expect_value(&mut args, &arg)?.into()),
+            "--gdb" => gdb_address = Some(expect_value(&mut args, &arg)?),
+            "--remote" => remote_address = Some(expect_value(&mut args, &arg)?),
+            "--heatmap" => options.heatmap = Some(expect_value(&mut args, &arg)?.into()),
+            "--profile" => options.profile = true,
+            "--script" => options.script = Some(expect_value(&mut args, &arg)?.into()),
+            "--reference" => reference = Some(PathBuf::from(expect_value(&mut args, &arg)?)),
+            "--trace" => {
+                trace_target = Some(match expect_value(&mut args, &arg)?.as_str() {
+                    "-" => TraceTarget::Stdout,
+                    path => TraceTarget::File(path.into()),
+                })
+            }
+            "--trace-ring" => {
+                trace_target = Some(TraceTarget::Ring(parse_value(
+                    &expect_value(&mut args, &arg)?,
+                    &arg,
+                )?))
+            }
+            "--trace-format" => {
+                trace_format = TraceFormat::from_name(&expect_value(&mut args, &arg)?)?
+            }
+            "--trace-range" => {
+                trace_filter.addresses =
+                    Some(TraceFilter::parse_range(&expect_value(&mut args, &arg)?)?)
+            }
+            "--trace-class" => {
+                trace_filter
+                    .classes
+                    .push(InstructionClass::from_name(&expect_value(
+                        &mut args, &arg,
+                    )?)?)
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => rom_source = Some(RomSource::from_arg(&arg)),
+        }
+    }
+
+    options.trace = trace_target.map(|target| TraceConfig {
+        target,
+        format: trace_format,
+        filter: trace_filter,
+    });
+
+    if let Some(action) = settings_action {
+        let rom_source = rom_source.ok_or_else(|| format!("settings expects a ROM\n{}", USAGE))?;
+        let rom = Rom::read(&rom_source)?;
+        return edit_settings(&action, &rom, &database, &keymap, &overrides);
+    }
+
+    // Without a config directory nothing is saved between runs
+    let store = SettingsStore::open(SettingsStore::default_path().ok().as_deref())?;
+    options.cheats = CheatStore::default_path().ok();
+    if let Some(address) = remote_address {
+        let rom = rom_source.as_ref().map(Rom::read).transpose()?;
+        return VM::run_remote(rom, &database, &overrides, &store, &options, &address);
+    }
+    if let Some(address) = gdb_address {
+        let rom_source = rom_source.ok_or_else(|| format!("--gdb expects a ROM\n{}", USAGE))?;
+        return VM::run_gdb(
+            &Rom::read(&rom_source)?,
+            &database,
+            &overrides,
+            &store,
+            &options,
+            &address,
+        );
+    }
+    if let Some(reference) = reference {
+        let rom_source =
+            rom_source.ok_or_else(|| format!("--reference expects a ROM\n{}", USAGE))?;
+        return VM::run_lockstep(
+            &Rom::read(&rom_source)?,
+            &database,
+            &overrides,
+            &store,
+            &options,
+            &ReferenceTrace::load(&reference)?,
+        );
+    }
+    if headless {
+        let rom_source =
+            rom_source.ok_or_else(|| format!("--headless expects a ROM\n{}", USAGE))?;
+        return VM::run_headless(
+            &Rom::read(&rom_source)?,
+            &database,
+            &overrides,
+            &store,
+            &options,
+        );
+    }
+    if let Some(mode) = terminal_mode {
+        if options.cheat_console {
+            return Err(
+                "--cheat-console reads stdin, which the terminal frontend uses".to_string(),
+            );
+        }
+        if options
+            .trace
+            .as_ref()
+            .is_some_and(|trace| matches!(trace.target, TraceTarget::Stdout | TraceTarget::Ring(_)))
+        {
+            return Err(
+                "--trace - and --trace-ring print to stdout, which the terminal frontend uses"
+                    .to_string(),
+            );
+        }
+        // There is no launcher in the terminal, F1 is not bound
+        let rom_source =
+            rom_source.ok_or_else(|| format!("--terminal expects a ROM\n{}", USAGE))?;
+        let rom = Rom::read(&rom_source)?;
+        let mut terminal = TerminalRenderer::new(mode)?;
+        VM::run_rom(
+            &rom,
+            &keymap,
+            &database,
+            &overrides,
+            &store,
+            &options,
+            &mut terminal,
+        )?;
+        return Ok(());
+    }
+    let mut renderer_context = SDLWrapper::initialize_sdl_renderer()?;
+    // Without a ROM on the command line, start in the launcher
+    let mut error = None;
+    loop {
+        let rom_source = match rom_source.take() {
+            Some(rom_source) => rom_source,
+            None => {
+                let mut launcher = Launcher::new(&rom_dir)?;
+                launcher.set_error(error.take());
+                match launcher.run(&mut renderer_context, &keymap)? {
+                    Some(rom) => RomSource::File(rom),
+                    None => return Ok(()),
+                }
+            }
+        };
+        let outcome = Rom::read(&rom_source).and_then(|rom| {
+            VM::run_rom(
+                &rom,
+                &keymap,
+                &database,
+                &overrides,
+                &store,
+                &options,
+                &mut renderer_context,
+            )
+        });
+        match outcome {
+            Ok(RunOutcome::Quit) => return Ok(()),
+            Ok(RunOutcome::Launcher) => {}
+            // Back to the launcher, which shows the error
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                renderer_context.set_status(None);
+                renderer_context.set_overlay(None);
+                error = Some(e);
+            }
+        }
+    }
 }
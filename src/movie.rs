@@ -0,0 +1,137 @@
+// Input movies, the keypad state of every frame of a run so it can be replayed
+// exactly, e.g. headless to record a clip of it. Text, one keypad change per
+// line as the frame it starts at and the keys held, bit n for key n, in hex:
+//
+//   # chip8 movie
+//   sha1 607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee
+//   seed 1700000000
+//   frames 600
+//   1 0000
+//   120 0002
+//   135 0000
+//
+// The seed is the CXNN seed of the run. Replays need the same ROM, settings
+// and --script, single instructions run with F7 and cheat console commands
+// are not recorded.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn mask(keys: [bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .fold(0, |mask, (key, &down)| mask | (down as u16) << key)
+}
+
+fn keys(mask: u16) -> [bool; 16] {
+    std::array::from_fn(|key| mask & (1 << key) != 0)
+}
+
+pub struct Movie {
+    pub sha1: String,
+    pub seed: u64,
+    // Frames recorded, the keypad is the player's again after them
+    pub frames: u64,
+    // Frame and keys from then on, by frame
+    changes: Vec<(u64, u16)>,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read movie {}: {}", path.display(), e))?;
+        let invalid = |number: usize| format!("Invalid movie {} line {}", path.display(), number);
+        let (mut sha1, mut seed, mut frames) = (None, None, None);
+        let mut changes: Vec<(u64, u16)> = vec![];
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').ok_or_else(|| invalid(index + 1))?;
+            let value = value.trim();
+            match key {
+                "sha1" => sha1 = Some(value.to_string()),
+                "seed" => seed = Some(value.parse().map_err(|_| invalid(index + 1))?),
+                "frames" => frames = Some(value.parse().map_err(|_| invalid(index + 1))?),
+                frame => {
+                    let frame: u64 = frame.parse().map_err(|_| invalid(index + 1))?;
+                    let keys = u16::from_str_radix(value, 16).map_err(|_| invalid(index + 1))?;
+                    if changes.last().is_some_and(|&(last, _)| last >= frame) {
+                        return Err(format!("{}, frames out of order", invalid(index + 1)));
+                    }
+                    changes.push((frame, keys));
+                }
+            }
+        }
+        let missing = |what: &str| format!("Movie {} has no {} line", path.display(), what);
+        Ok(Movie {
+            sha1: sha1.ok_or_else(|| missing("sha1"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            frames: frames.ok_or_else(|| missing("frames"))?,
+            changes,
+        })
+    }
+
+    // Keys held during a frame, counted from 1, None after the movie
+    pub fn keys(&self, frame: u64) -> Option<[bool; 16]> {
+        if frame > self.frames {
+            return None;
+        }
+        let index = self.changes.partition_point(|&(start, _)| start <= frame);
+        let mask = index
+            .checked_sub(1)
+            .map_or(0, |index| self.changes[index].1);
+        Some(keys(mask))
+    }
+}
+
+pub struct MovieRecorder {
+    path: PathBuf,
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn new(path: &Path, sha1: &str, seed: u64) -> Self {
+        MovieRecorder {
+            path: path.to_path_buf(),
+            movie: Movie {
+                sha1: sha1.to_string(),
+                seed,
+                frames: 0,
+                changes: vec![],
+            },
+        }
+    }
+
+    // The keys of a frame, called for every frame in order
+    pub fn record(&mut self, frame: u64, keys: [bool; 16]) {
+        let mask = mask(keys);
+        let movie = &mut self.movie;
+        if movie.changes.last().is_none_or(|&(_, last)| last != mask) {
+            movie.changes.push((frame, mask));
+        }
+        movie.frames = frame;
+    }
+
+    // Returns a line saying what was saved
+    pub fn finish(self) -> Result<String, String> {
+        let movie = &self.movie;
+        let mut content = format!(
+            "# chip8 movie\nsha1 {}\nseed {}\nframes {}\n",
+            movie.sha1, movie.seed, movie.frames
+        );
+        for (frame, mask) in &movie.changes {
+            content.push_str(&format!("{} {:04x}\n", frame, mask));
+        }
+        fs::write(&self.path, content)
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        Ok(format!(
+            "Saved {} frames of input to {}",
+            movie.frames,
+            self.path.display()
+        ))
+    }
+}
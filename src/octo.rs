@@ -0,0 +1,917 @@
+// Assembler for the Octo language (https://github.com/JohnEarnest/Octo), used
+// to build the programs stored in Octo cartridges.
+//
+// Covers labels, :const, :alias, :org, :next, :unpack, :call, :byte, :macro,
+// :calc (numbers, names, parentheses, arithmetic and bitwise operators,
+// evaluated right to left like Octo), the CHIP-8, SUPER-CHIP and XO-CHIP
+// statements and the structured if/then, if/begin/else/end and
+// loop/while/again forms. :stringmode, :assert and :pointer are rejected.
+
+use std::collections::HashMap;
+
+use crate::constants::PROGRAM_START;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+#[derive(Clone)]
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Value {
+    Known(i64),
+    // Label used before being defined
+    Forward(String),
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // Low 12 bits of the instruction at the address
+    Address,
+    // 16 bit word following F000 (i := long)
+    Long,
+    // v1 := NN of :unpack, and the low nibble of the v0 := NN before it
+    Unpack,
+}
+
+impl FixupKind {
+    fn limit(self) -> i64 {
+        match self {
+            FixupKind::Address | FixupKind::Unpack => 0xFFF,
+            FixupKind::Long => 0xFFFF,
+        }
+    }
+}
+
+struct Fixup {
+    address: usize,
+    label: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Key,
+    NotKey,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+struct Condition {
+    register: u8,
+    comparison: Comparison,
+    // Register or immediate for the comparisons that take one
+    operand: Option<Result<u8, u8>>,
+}
+
+struct Loop {
+    start: usize,
+    // Jumps out of the loop emitted by while, patched at again
+    exits: Vec<usize>,
+}
+
+struct Assembler {
+    // Remaining tokens, the next one is at the end
+    tokens: Vec<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    next_label: Option<String>,
+    branches: Vec<usize>,
+    loops: Vec<Loop>,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |text| Token {
+                text: text.to_string(),
+                line: index + 1,
+            })
+        })
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn binary_operation(operator: &str, left: f64, right: f64) -> Result<f64, String> {
+    let (l, r) = (left as i64, right as i64);
+    // Cartridges are untrusted, % 0 and out of range shifts are errors
+    let integer = |value: Option<i64>| {
+        value
+            .map(|value| value as f64)
+            .ok_or_else(|| format!("invalid operands {} {} {}", l, operator, r))
+    };
+    let shift = u32::try_from(r).ok();
+    match operator {
+        "+" => Ok(left + right),
+        "-" => Ok(left - right),
+        "*" => Ok(left * right),
+        "/" => Ok(left / right),
+        "%" => integer(l.checked_rem(r)),
+        "&" => Ok((l & r) as f64),
+        "|" => Ok((l | r) as f64),
+        "^" => Ok((l ^ r) as f64),
+        "<<" => integer(shift.and_then(|shift| l.checked_shl(shift))),
+        ">>" => integer(shift.and_then(|shift| l.checked_shr(shift))),
+        "min" => Ok(left.min(right)),
+        "max" => Ok(left.max(right)),
+        _ => Err(format!("unsupported calc operator {}", operator)),
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let mut assembler = Assembler {
+        tokens,
+        line: 0,
+        rom: vec![],
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: vec![],
+        next_label: None,
+        branches: vec![],
+        loops: vec![],
+    };
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+impl Assembler {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, String> {
+        Err(format!("Octo line {}: {}", self.line, message.into()))
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of program"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected {}, found {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        // Octo programs start with an implicit jump to main
+        self.fixups.push(Fixup {
+            address: self.here,
+            label: "main".to_string(),
+            kind: FixupKind::Address,
+            line: 0,
+        });
+        self.instruction(0x1000);
+        while !self.tokens.is_empty() {
+            let token = self.next()?;
+            self.statement(&token)?;
+        }
+        if !self.branches.is_empty() || !self.loops.is_empty() {
+            return self.error("missing end or again");
+        }
+        if !self.labels.contains_key("main") {
+            return Err("Octo program has no main label".to_string());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(&fixup.label) else {
+                return Err(format!(
+                    "Octo line {}: undefined name {}",
+                    fixup.line, fixup.label
+                ));
+            };
+            if address as i64 > fixup.kind.limit() {
+                return Err(format!(
+                    "Octo line {}: address {:#X} of {} is out of range",
+                    fixup.line, address, fixup.label
+                ));
+            }
+            self.patch(fixup.address, fixup.kind, address);
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, value: u8) {
+        let index = self.here - PROGRAM_START;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = value;
+        self.here += 1;
+    }
+
+    fn instruction(&mut self, word: u16) {
+        if let Some(label) = self.next_label.take() {
+            self.labels.insert(label, self.here + 1);
+        }
+        let [high, low] = word.to_be_bytes();
+        self.byte(high);
+        self.byte(low);
+    }
+
+    fn patch(&mut self, address: usize, kind: FixupKind, value: usize) {
+        let index = address - PROGRAM_START;
+        match kind {
+            FixupKind::Address => {
+                self.rom[index] = (self.rom[index] & 0xF0) | ((value >> 8) & 0x0F) as u8;
+                self.rom[index + 1] = value as u8;
+            }
+            FixupKind::Long => {
+                self.rom[index] = (value >> 8) as u8;
+                self.rom[index + 1] = value as u8;
+            }
+            FixupKind::Unpack => {
+                self.rom[index - 1] |= ((value >> 8) & 0x0F) as u8;
+                self.rom[index + 1] = value as u8;
+            }
+        }
+    }
+
+    // Emits an instruction whose address may not be known yet
+    fn address_instruction(&mut self, opcode: u16, kind: FixupKind) -> Result<(), String> {
+        let at = self.here;
+        match self.value()? {
+            Value::Known(address) => {
+                self.check_address(address, kind)?;
+                self.instruction(opcode);
+                self.patch(at, kind, address as usize);
+            }
+            Value::Forward(label) => {
+                self.instruction(opcode);
+                self.fixups.push(Fixup {
+                    address: at,
+                    label,
+                    kind,
+                    line: self.line,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let token = self.next()?;
+        if token == "{" {
+            return Ok(Value::Known(self.calc()? as i64));
+        }
+        if let Some(number) = parse_number(&token) {
+            return Ok(Value::Known(number));
+        }
+        if let Some(&constant) = self.constants.get(&token) {
+            return Ok(Value::Known(constant as i64));
+        }
+        if let Some(&address) = self.labels.get(&token) {
+            return Ok(Value::Known(address as i64));
+        }
+        if self.register_of(&token).is_some() {
+            return self.error(format!("expected a number, found register {}", token));
+        }
+        Ok(Value::Forward(token))
+    }
+
+    fn known_value(&mut self) -> Result<i64, String> {
+        match self.value()? {
+            Value::Known(value) => Ok(value),
+            Value::Forward(name) => self.error(format!("{} must be defined before this use", name)),
+        }
+    }
+
+    // A value that fits in 8 bits, negative ones as two's complement
+    fn byte_value(&mut self) -> Result<u8, String> {
+        let value = self.known_value()?;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn check_address(&self, address: i64, kind: FixupKind) -> Result<(), String> {
+        if !(0..=kind.limit()).contains(&address) {
+            return self.error(format!("address {:#X} is out of range", address));
+        }
+        Ok(())
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        parse_register(token).or_else(|| self.aliases.get(token).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.register_of(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register, found {}", token)),
+        }
+    }
+
+    // Everything up to the closing brace, the opening one was already read
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 1;
+        let mut body = vec![];
+        loop {
+            let Some(token) = self.tokens.pop() else {
+                return self.error("missing }");
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn calc(&mut self) -> Result<f64, String> {
+        let mut expression = self.braced_tokens()?;
+        expression.reverse();
+        let value = self.calc_expression(&mut expression)?;
+        if let Some(token) = expression.pop() {
+            return self.error(format!("unexpected {} in calc expression", token.text));
+        }
+        Ok(value)
+    }
+
+    // No precedence, operators apply right to left
+    fn calc_expression(&self, tokens: &mut Vec<Token>) -> Result<f64, String> {
+        let left = self.calc_term(tokens)?;
+        match tokens.last().map(|token| token.text.clone()) {
+            Some(operator) if operator != ")" => {
+                tokens.pop();
+                let right = self.calc_expression(tokens)?;
+                match binary_operation(&operator, left, right) {
+                    Ok(value) => Ok(value),
+                    Err(message) => self.error(message),
+                }
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn calc_term(&self, tokens: &mut Vec<Token>) -> Result<f64, String> {
+        let Some(token) = tokens.pop() else {
+            return self.error("incomplete calc expression");
+        };
+        let text = token.text.as_str();
+        match text {
+            "(" => {
+                let value = self.calc_expression(tokens)?;
+                match tokens.pop() {
+                    Some(token) if token.text == ")" => Ok(value),
+                    _ => self.error("missing ) in calc expression"),
+                }
+            }
+            "-" => Ok(-self.calc_term(tokens)?),
+            "~" => Ok(!(self.calc_term(tokens)? as i64) as f64),
+            "!" => Ok(if self.calc_term(tokens)? == 0.0 {
+                1.0
+            } else {
+                0.0
+            }),
+            "HERE" => Ok(self.here as f64),
+            _ => {
+                if let Some(number) = parse_number(text) {
+                    Ok(number as f64)
+                } else if let Some(&constant) = self.constants.get(text) {
+                    Ok(constant)
+                } else if let Some(&address) = self.labels.get(text) {
+                    Ok(address as f64)
+                } else {
+                    self.error(format!("{} is not defined yet", text))
+                }
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let register = self.register()?;
+        let operator = self.next()?;
+        let comparison = match operator.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return self.error(format!("unknown comparison {}", operator)),
+        };
+        let operand = match comparison {
+            Comparison::Key | Comparison::NotKey => None,
+            _ => {
+                let register_operand = self.peek().and_then(|token| self.register_of(token));
+                Some(match register_operand {
+                    Some(other) => {
+                        self.next()?;
+                        Ok(other)
+                    }
+                    None => Err(self.byte_value()?),
+                })
+            }
+        };
+        Ok(Condition {
+            register,
+            comparison,
+            operand,
+        })
+    }
+
+    // The last instruction skips the next one when the condition is false,
+    // or when it is true if inverted
+    fn emit_condition(&mut self, condition: &Condition, inverted: bool) {
+        let x = condition.register as u16;
+        let (skip_if_false, skip_if_true) = match (condition.comparison, condition.operand) {
+            (Comparison::Equal, Some(Err(n))) => {
+                (0x4000 | x << 8 | n as u16, 0x3000 | x << 8 | n as u16)
+            }
+            (Comparison::Equal, Some(Ok(y))) => (
+                0x9000 | x << 8 | (y as u16) << 4,
+                0x5000 | x << 8 | (y as u16) << 4,
+            ),
+            (Comparison::NotEqual, Some(Err(n))) => {
+                (0x3000 | x << 8 | n as u16, 0x4000 | x << 8 | n as u16)
+            }
+            (Comparison::NotEqual, Some(Ok(y))) => (
+                0x5000 | x << 8 | (y as u16) << 4,
+                0x9000 | x << 8 | (y as u16) << 4,
+            ),
+            (Comparison::Key, _) => (0xE0A1 | x << 8, 0xE09E | x << 8),
+            (Comparison::NotKey, _) => (0xE09E | x << 8, 0xE0A1 | x << 8),
+            (comparison, Some(operand)) => {
+                // Ordered comparisons go through VF: the subtraction leaves
+                // VF = 1 when there is no borrow
+                let (first, second) = match (comparison, operand) {
+                    (Comparison::Less | Comparison::GreaterOrEqual, Ok(y)) => {
+                        (0x8F00 | x << 4, 0x8F05 | (y as u16) << 4)
+                    }
+                    (_, Ok(y)) => (0x8F00 | (y as u16) << 4, 0x8F05 | x << 4),
+                    (Comparison::Less | Comparison::GreaterOrEqual, Err(n)) => {
+                        (0x6F00 | n as u16, 0x8F07 | x << 4)
+                    }
+                    (_, Err(n)) => (0x6F00 | n as u16, 0x8F05 | x << 4),
+                };
+                self.instruction(first);
+                self.instruction(second);
+                match comparison {
+                    Comparison::Less | Comparison::Greater => (0x4F00, 0x3F00),
+                    _ => (0x3F00, 0x4F00),
+                }
+            }
+            (_, None) => unreachable!("comparisons other than key take an operand"),
+        };
+        self.instruction(if inverted {
+            skip_if_true
+        } else {
+            skip_if_false
+        });
+    }
+
+    fn jump_placeholder(&mut self) -> usize {
+        let at = self.here;
+        self.instruction(0x1000);
+        at
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        if let Some(x) = self.register_of(token) {
+            return self.register_statement(x as u16);
+        }
+        if let Some(number) = parse_number(token) {
+            if !(-128..=255).contains(&number) {
+                return self.error(format!("{} does not fit in a byte", number));
+            }
+            self.byte(number as u8);
+            return Ok(());
+        }
+        if let Some(definition) = self.macros.get(token).cloned() {
+            return self.expand_macro(definition);
+        }
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.clone(), self.here).is_some() {
+                    return self.error(format!("label {} is defined twice", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.known_value()?;
+                self.constants.insert(name, value as f64);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":org" => {
+                let address = self.known_value()?;
+                if address < PROGRAM_START as i64 {
+                    return self.error(format!(":org {:#X} is below the program start", address));
+                }
+                self.here = address as usize;
+            }
+            ":next" => self.next_label = Some(self.next()?),
+            ":unpack" => {
+                let nibble = self.known_value()? as u16 & 0x0F;
+                // v0 := high nibble and address bits 8-11, v1 := address low byte
+                self.instruction(0x6000 | nibble << 4);
+                self.address_instruction(0x6100, FixupKind::Unpack)?;
+            }
+            ":call" => self.address_instruction(0x2000, FixupKind::Address)?,
+            ":byte" => {
+                let value = self.byte_value()?;
+                self.byte(value);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut arguments = vec![];
+                loop {
+                    let argument = self.next()?;
+                    if argument == "{" {
+                        break;
+                    }
+                    arguments.push(argument);
+                }
+                let body = self.braced_tokens()?;
+                self.macros.insert(name, Macro { arguments, body });
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":stringmode" | ":assert" | ":pointer" => {
+                return self.error(format!("{} is not supported", token));
+            }
+            "clear" => self.instruction(0x00E0),
+            "return" | ";" => self.instruction(0x00EE),
+            "hires" => self.instruction(0x00FF),
+            "lores" => self.instruction(0x00FE),
+            "scroll-down" => {
+                let n = self.known_value()? as u16;
+                self.instruction(0x00C0 | (n & 0x0F));
+            }
+            "scroll-up" => {
+                let n = self.known_value()? as u16;
+                self.instruction(0x00D0 | (n & 0x0F));
+            }
+            "scroll-right" => self.instruction(0x00FB),
+            "scroll-left" => self.instruction(0x00FC),
+            "exit" => self.instruction(0x00FD),
+            "audio" => self.instruction(0xF002),
+            "plane" => {
+                let n = self.known_value()? as u16;
+                self.instruction(0xF001 | (n & 0x0F) << 8);
+            }
+            "jump" => self.address_instruction(0x1000, FixupKind::Address)?,
+            "jump0" => self.address_instruction(0xB000, FixupKind::Address)?,
+            "native" => self.address_instruction(0x0000, FixupKind::Address)?,
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.known_value()? as u16;
+                self.instruction(0xD000 | x << 8 | y << 4 | (n & 0x0F));
+            }
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.instruction(0xF033 | x << 8);
+            }
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let low = if token == "save" { 0x2 } else { 0x3 };
+                    self.instruction(0x5000 | x << 8 | y << 4 | low);
+                } else {
+                    let low = if token == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xF000 | x << 8 | low);
+                }
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.register()? as u16;
+                let low = if token == "saveflags" { 0x75 } else { 0x85 };
+                self.instruction(0xF000 | x << 8 | low);
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let low = match token {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.instruction(0xF000 | x << 8 | low);
+            }
+            "i" => self.i_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => {
+                        self.emit_condition(&condition, false);
+                        let next = self.next()?;
+                        self.statement(&next)?;
+                    }
+                    "begin" => {
+                        self.emit_condition(&condition, true);
+                        let jump = self.jump_placeholder();
+                        self.branches.push(jump);
+                    }
+                    other => return self.error(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let Some(previous) = self.branches.pop() else {
+                    return self.error("else without if ... begin");
+                };
+                let jump = self.jump_placeholder();
+                self.patch(previous, FixupKind::Address, self.here);
+                self.branches.push(jump);
+            }
+            "end" => {
+                let Some(previous) = self.branches.pop() else {
+                    return self.error("end without if ... begin");
+                };
+                self.patch(previous, FixupKind::Address, self.here);
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: vec![],
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return self.error("while outside of a loop");
+                }
+                let condition = self.condition()?;
+                self.emit_condition(&condition, true);
+                let jump = self.jump_placeholder();
+                if let Some(current) = self.loops.last_mut() {
+                    current.exits.push(jump);
+                }
+            }
+            "again" => {
+                let Some(current) = self.loops.pop() else {
+                    return self.error("again without loop");
+                };
+                let jump = self.jump_placeholder();
+                self.patch(jump, FixupKind::Address, current.start);
+                for exit in current.exits {
+                    self.patch(exit, FixupKind::Address, self.here);
+                }
+            }
+            _ => {
+                // Anything else is a subroutine call
+                self.tokens.push(Token {
+                    text: token.to_string(),
+                    line: self.line,
+                });
+                self.address_instruction(0x2000, FixupKind::Address)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, definition: Macro) -> Result<(), String> {
+        let mut values = HashMap::new();
+        for argument in &definition.arguments {
+            values.insert(argument.clone(), self.next()?);
+        }
+        for token in definition.body.into_iter().rev() {
+            let text = values.get(&token.text).cloned().unwrap_or(token.text);
+            self.tokens.push(Token {
+                text,
+                line: token.line,
+            });
+        }
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), String> {
+        match self.next()?.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let low = if self.next()? == "hex" { 0x29 } else { 0x30 };
+                    let x = self.register()? as u16;
+                    self.instruction(0xF000 | x << 8 | low);
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.instruction(0xF000);
+                    let at = self.here;
+                    self.byte(0);
+                    self.byte(0);
+                    match self.value()? {
+                        Value::Known(address) => {
+                            self.check_address(address, FixupKind::Long)?;
+                            self.patch(at, FixupKind::Long, address as usize)
+                        }
+                        Value::Forward(label) => self.fixups.push(Fixup {
+                            address: at,
+                            label,
+                            kind: FixupKind::Long,
+                            line: self.line,
+                        }),
+                    }
+                }
+                _ => self.address_instruction(0xA000, FixupKind::Address)?,
+            },
+            "+=" => {
+                let x = self.register()? as u16;
+                self.instruction(0xF01E | x << 8);
+            }
+            other => return self.error(format!("unknown operator i {}", other)),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let register_operand = self.peek().and_then(|token| self.register_of(token));
+        if let Some(y) = register_operand {
+            self.next()?;
+            let low = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("unknown operator {}", operator)),
+            };
+            self.instruction(0x8000 | x << 8 | (y as u16) << 4 | low);
+            return Ok(());
+        }
+        match (operator.as_str(), self.peek()) {
+            (":=", Some("delay")) => {
+                self.next()?;
+                self.instruction(0xF007 | x << 8);
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                self.instruction(0xF00A | x << 8);
+            }
+            (":=", Some("random")) => {
+                self.next()?;
+                let mask = self.byte_value()? as u16;
+                self.instruction(0xC000 | x << 8 | mask);
+            }
+            (":=", _) => {
+                let value = self.byte_value()? as u16;
+                self.instruction(0x6000 | x << 8 | value);
+            }
+            ("+=", _) => {
+                let value = self.byte_value()? as u16;
+                self.instruction(0x7000 | x << 8 | value);
+            }
+            ("-=", _) => {
+                let value = self.byte_value()?.wrapping_neg() as u16;
+                self.instruction(0x7000 | x << 8 | value);
+            }
+            _ => return self.error(format!("unknown operator {}", operator)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn labels_and_calls() {
+        let source = ": main\n  v0 := 5\n  sub\n  jump main\n: sub\n  return\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x02, 0x60, 0x05, 0x22, 0x08, 0x12, 0x02, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn calc_runs_right_to_left() {
+        let source = ":const SPEED 3\n:calc DOUBLE { SPEED * 2 + 1 }\n\
+                      : main\n  v1 := DOUBLE\n  v2 += { 1 << 4 }\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x02, 0x61, 0x09, 0x72, 0x10]
+        );
+    }
+
+    #[test]
+    fn if_then_and_else() {
+        let source = ": main\n  if v0 == 1 then v1 := 2\n\
+                      if v0 != v2 begin v3 := 4 else v3 := 5 end\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x12, 0x02, 0x40, 0x01, 0x61, 0x02, 0x90, 0x20, 0x12, 0x0E, 0x63, 0x04, 0x12, 0x10,
+                0x63, 0x05
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = ": main\n  loop\n    v0 += 1\n    while v0 != 10\n  again\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x02, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn bytes_and_sprites() {
+        let source = ": main\n  i := data\n  sprite v0 v1 2\n\
+                      : data\n  :byte 0x81\n  0b11000011\n  :byte { 2 + 3 }\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x02, 0xA2, 0x06, 0xD0, 0x12, 0x81, 0xC3, 0x05]
+        );
+    }
+
+    #[test]
+    fn out_of_range_values_are_errors() {
+        for source in [
+            ": main v0 := 300",
+            ": main :byte -129",
+            ": main 256",
+            ": main jump 0x1000",
+            ": main i := long 0x10000",
+            ": main jump far\n:org 0x1000\n: far",
+            ":org 0x100\n: main",
+            ":calc X { 1 % 0 }\n: main",
+            ":calc X { 1 << 64 }\n: main",
+            ":calc X { 1 >> -1 }\n: main",
+        ] {
+            assert!(assemble(source).is_err(), "{:?} assembled", source);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Debug overlay: the fault if the VM halted, registers, timers, stack, code around PC and memory at I
+
+use crate::instruction;
+use crate::vm::VM;
+
+// Instructions shown before and after PC
+const CODE_CONTEXT: u16 = 6;
+const MEMORY_ROWS: usize = 8;
+const MEMORY_ROW_BYTES: usize = 8;
+
+pub fn lines(vm: &VM) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(fault) = vm.fault() {
+        lines.push(fault.to_string());
+    }
+    for (row, values) in vm.registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+            .collect();
+        lines.push(registers.join("  "));
+    }
+    lines.push(format!("I {:04X}  PC {:04X}", vm.i(), vm.pc()));
+    let (delay, sound) = vm.timers();
+    lines.push(format!("DT {:02X}  ST {:02X}", delay, sound));
+    let stack: Vec<String> = vm
+        .stack()
+        .iter()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    lines.push(format!("STACK {}", stack.join(" ")));
+
+    lines.push(String::new());
+    let pc = vm.pc();
+    let first = pc.saturating_sub(CODE_CONTEXT * 2);
+    for address in (first..=pc + CODE_CONTEXT * 2).step_by(2) {
+        let Some(opcode) = vm.opcode_at(address) else {
+            break;
+        };
+        let marker = if address == pc { '>' } else { ' ' };
+        lines.push(format!(
+            "{}{:04X} {:04X} {}",
+            marker,
+            address,
+            opcode,
+            instruction::disassemble(opcode)
+        ));
+    }
+
+    lines.push(String::new());
+    let memory = vm.memory();
+    for row in 0..MEMORY_ROWS {
+        let start = vm.i() as usize + row * MEMORY_ROW_BYTES;
+        let Some(bytes) = memory.get(start..start + MEMORY_ROW_BYTES) else {
+            break;
+        };
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        lines.push(format!("{:04X} {}", start, bytes.join(" ")));
+    }
+    lines
+}
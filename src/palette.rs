@@ -0,0 +1,80 @@
+// Two colour palette used to draw display_bits
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+// Stored as "#000000,#FAFAFA" in settings files
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: [0, 0, 0],
+            foreground: [250, 250, 250],
+        }
+    }
+}
+
+// "#RRGGBB", the leading # is optional
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("Invalid colour {}, expected #RRGGBB", color))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok([r, g, b])
+}
+
+impl Palette {
+    // "#000000,#FFFFFF", background first
+    pub fn parse(colors: &str) -> Result<Self, String> {
+        let (background, foreground) = colors
+            .split_once(',')
+            .ok_or_else(|| format!("Expected background,foreground colours, got {}", colors))?;
+        Ok(Palette {
+            background: parse_color(background)?,
+            foreground: parse_color(foreground)?,
+        })
+    }
+
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        if pixel == 0 {
+            self.background
+        } else {
+            self.foreground
+        }
+    }
+}
+
+impl Display for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [br, bg, bb] = self.background;
+        let [fr, fg, fb] = self.foreground;
+        write!(
+            f,
+            "#{:02X}{:02X}{:02X},#{:02X}{:02X}{:02X}",
+            br, bg, bb, fr, fg, fb
+        )
+    }
+}
+
+impl TryFrom<String> for Palette {
+    type Error = String;
+
+    fn try_from(colors: String) -> Result<Self, Self::Error> {
+        Palette::parse(&colors)
+    }
+}
+
+impl From<Palette> for String {
+    fn from(palette: Palette) -> Self {
+        palette.to_string()
+    }
+}
@@ -0,0 +1,55 @@
+// Target platform of a ROM, guessed from its file extension or its chip-8-database entry
+
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "ch8" => Some(Platform::Chip8),
+            "sc8" => Some(Platform::SuperChip),
+            "xo8" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // Platform ids used by the chip-8-database, see database/platforms.json
+    pub fn from_database_id(id: &str) -> Option<Self> {
+        match id {
+            "originalChip8" | "hybridVIP" | "modernChip8" | "chip48" => Some(Platform::Chip8),
+            "superchip1" | "superchip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // Quirks profile used when the database doesn't know the ROM
+    pub fn default_database_id(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "originalChip8",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // Only CHIP-8 opcodes are implemented, SUPER-CHIP and XO-CHIP programs
+    // would fault on their first extended instruction
+    pub fn is_supported(self) -> bool {
+        self == Platform::Chip8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Execution profiler: instructions per address and per subroutine, and the
+// frames that had no time to spare.
+//
+// Subroutines are followed through 2NNN and 00EE. Exclusive counts are the
+// instructions of the subroutine itself, inclusive counts add everything it
+// called. A frame is over budget when it ran all its instructions without
+// waiting: no FX0A, no vblank wait and no look at the delay timer (FX07),
+// which is how CHIP-8 games usually idle until the next frame.
+
+use std::collections::HashMap;
+
+use crate::constants::{MEMORY_SIZE, PROGRAM_START};
+use crate::instruction::{disassemble, Instruction};
+
+// Hot spots listed in the report
+const REPORT_TOP: usize = 20;
+// Frames over budget listed by number
+const REPORT_FRAMES: usize = 10;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+// A subroutine being executed, with the instruction count when it was entered
+struct Call {
+    entry: u16,
+    entered_at: u64,
+}
+
+pub struct Profiler {
+    counts: Vec<u64>,
+    opcodes: Vec<u16>,
+    instructions: u64,
+    subroutines: HashMap<u16, Subroutine>,
+    calls: Vec<Call>,
+    frames: u64,
+    idle: bool,
+    over_budget: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            counts: vec![0; MEMORY_SIZE],
+            opcodes: vec![0; MEMORY_SIZE],
+            instructions: 0,
+            subroutines: HashMap::new(),
+            // The program itself is the outermost subroutine
+            calls: vec![Call {
+                entry: PROGRAM_START as u16,
+                entered_at: 0,
+            }],
+            frames: 0,
+            idle: false,
+            over_budget: vec![],
+        }
+    }
+
+    // Called by the VM after each executed instruction
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16) {
+        let index = pc as usize % MEMORY_SIZE;
+        self.counts[index] += 1;
+        // Self-modifying code keeps the last opcode seen at the address
+        self.opcodes[index] = opcode;
+        self.instructions += 1;
+        if let Some(call) = self.calls.last() {
+            self.subroutines.entry(call.entry).or_default().exclusive += 1;
+        }
+        match Instruction::from_u16(opcode) {
+            Some(Instruction::Call { adress }) => {
+                self.subroutines.entry(adress).or_default().calls += 1;
+                self.calls.push(Call {
+                    entry: adress,
+                    entered_at: self.instructions,
+                });
+            }
+            // The outermost entry stays, a stray 00EE cannot end the program
+            Some(Instruction::Return) if self.calls.len() > 1 => {
+                if let Some(call) = self.calls.pop() {
+                    self.add_inclusive(&call);
+                }
+            }
+            Some(Instruction::ReadDelay { .. } | Instruction::WaitKey { .. }) => self.idle = true,
+            _ => {}
+        }
+    }
+
+    // Called by the VM at the end of each frame, full when every cycle ran an
+    // instruction, without vblank or key waits
+    pub(crate) fn end_frame(&mut self, full: bool) {
+        self.frames += 1;
+        if full && !self.idle {
+            self.over_budget.push(self.frames);
+        }
+        self.idle = false;
+    }
+
+    fn add_inclusive(&mut self, call: &Call) {
+        let elapsed = self.instructions - call.entered_at;
+        self.subroutines.entry(call.entry).or_default().inclusive += elapsed;
+    }
+
+    pub fn report(mut self) -> String {
+        // Subroutines still running count up to now
+        for call in std::mem::take(&mut self.calls) {
+            self.add_inclusive(&call);
+        }
+        let total = self.instructions.max(1) as f64;
+        let mut report = format!(
+            "Profile: {} instructions in {} frames\n\nHot spots:\n{:<8}{:>12}{:>8}  instruction\n",
+            self.instructions, self.frames, "address", "count", "share"
+        );
+        let mut hot: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(address, count) in hot.iter().take(REPORT_TOP) {
+            report += &format!(
+                "{:<#8X}{:>12}{:>7.1}%  {}\n",
+                address,
+                count,
+                count as f64 * 100.0 / total,
+                disassemble(self.opcodes[address])
+            );
+        }
+
+        report += &format!(
+            "\nSubroutines:\n{:<8}{:>8}{:>20}{:>20}\n",
+            "entry", "calls", "inclusive", "exclusive"
+        );
+        let mut subroutines: Vec<(u16, Subroutine)> = self.subroutines.into_iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        for (entry, subroutine) in subroutines {
+            report += &format!(
+                "{:<#8X}{:>8}{:>12}{:>7.1}%{:>12}{:>7.1}%\n",
+                entry,
+                subroutine.calls,
+                subroutine.inclusive,
+                subroutine.inclusive as f64 * 100.0 / total,
+                subroutine.exclusive,
+                subroutine.exclusive as f64 * 100.0 / total
+            );
+        }
+
+        report += &format!(
+            "\nFrames over budget: {} of {}",
+            self.over_budget.len(),
+            self.frames
+        );
+        if !self.over_budget.is_empty() {
+            let frames: Vec<String> = self
+                .over_budget
+                .iter()
+                .take(REPORT_FRAMES)
+                .map(u64::to_string)
+                .collect();
+            let more = if self.over_budget.len() > REPORT_FRAMES {
+                ", ..."
+            } else {
+                ""
+            };
+            report += &format!(" (frames {}{})", frames.join(", "), more);
+        }
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
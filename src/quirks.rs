@@ -0,0 +1,73 @@
+// Behaviour differences between CHIP-8 interpreters, named like the chip-8-database quirks
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of loading VY first
+    pub shift: bool,
+    // FX55/FX65 increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    // FX55/FX65 leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump: bool,
+    // DXYN waits for the next frame before drawing
+    pub vblank: bool,
+    // 8XY1/8XY2/8XY3 reset VF
+    pub logic: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 7] = [
+        "shift",
+        "memoryIncrementByX",
+        "memoryLeaveIUnchanged",
+        "wrap",
+        "jump",
+        "vblank",
+        "logic",
+    ];
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift),
+            "memoryIncrementByX" => Some(&mut self.memory_increment_by_x),
+            "memoryLeaveIUnchanged" => Some(&mut self.memory_leave_i_unchanged),
+            "wrap" => Some(&mut self.wrap),
+            "jump" => Some(&mut self.jump),
+            "vblank" => Some(&mut self.vblank),
+            "logic" => Some(&mut self.logic),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let flag = self.flag(name).ok_or_else(|| {
+            format!(
+                "Unknown quirk {}, expected one of {}",
+                name,
+                Self::NAMES.join(", ")
+            )
+        })?;
+        *flag = value;
+        Ok(())
+    }
+
+    // "name=on" / "name=off", as given on the command line
+    pub fn parse_assignment(assignment: &str) -> Result<(String, bool), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected quirk=on|off, got {}", assignment))?;
+        let value = match value {
+            "on" | "true" | "1" => true,
+            "off" | "false" | "0" => false,
+            _ => return Err(format!("Expected on or off for quirk {}", name)),
+        };
+        Self::default().set(name, value)?;
+        Ok((name.to_string(), value))
+    }
+}
@@ -0,0 +1,109 @@
+// Gameplay recording, one frame per 60Hz tick.
+//
+// Files ending in .gif become animated GIFs in the active palette at 30fps,
+// every other frame, since viewers slow down delays under 2cs. Anything
+// else is a raw RGB24 frame dump that can be fed to an encoder, e.g.
+// ffmpeg -f rawvideo -pixel_format rgb24 -video_size 64x32 -framerate 60 -i pong.rgb pong.mp4
+// (video_size is 64x32 times the recording scale).
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::palette::Palette;
+use crate::screenshot;
+
+// GIF delays are in hundredths of a second, two 60Hz frames
+const GIF_DELAY: u16 = 2;
+
+enum Output {
+    Gif(Encoder<BufWriter<File>>),
+    Raw(BufWriter<File>),
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    output: Output,
+    palette: Palette,
+    scale: usize,
+    frames: usize,
+}
+
+impl Recorder {
+    // Each display pixel becomes scale x scale pixels in the recording
+    pub fn create(path: &Path, palette: Palette, scale: u32) -> Result<Self, String> {
+        let scale = scale.max(1) as usize;
+        let file = BufWriter::new(
+            File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?,
+        );
+        let is_gif = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        let output = if is_gif {
+            let invalid = |e: gif::EncodingError| format!("Cannot write {}: {}", path.display(), e);
+            let colors = [palette.color(0), palette.color(1)].concat();
+            let mut encoder = Encoder::new(
+                file,
+                (CHIP8_WIDTH * scale) as u16,
+                (CHIP8_HEIGHT * scale) as u16,
+                &colors,
+            )
+            .map_err(invalid)?;
+            encoder.set_repeat(Repeat::Infinite).map_err(invalid)?;
+            Output::Gif(encoder)
+        } else {
+            Output::Raw(file)
+        };
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            output,
+            palette,
+            scale,
+            frames: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> Result<(), String> {
+        let (scale, palette) = (self.scale, self.palette);
+        let written = match &mut self.output {
+            Output::Gif(_) if self.frames % 2 == 1 => Ok(()),
+            Output::Gif(encoder) => {
+                let indices = screenshot::scaled(pixels, scale, |value| [(value != 0) as u8]);
+                let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+                let mut frame =
+                    Frame::from_indexed_pixels(width as u16, height as u16, indices, None);
+                frame.delay = GIF_DELAY;
+                encoder.write_frame(&frame).map_err(|e| e.to_string())
+            }
+            Output::Raw(file) => {
+                let data = screenshot::scaled(pixels, scale, |value| palette.color(value));
+                file.write_all(&data).map_err(|e| e.to_string())
+            }
+        };
+        written.map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Returns a line saying what was saved
+    pub fn finish(self) -> Result<String, String> {
+        let invalid = |e: String| format!("Cannot write {}: {}", self.path.display(), e);
+        match self.output {
+            Output::Gif(encoder) => encoder
+                .into_inner()
+                .and_then(|mut file| Ok(file.flush()?))
+                .map_err(|e| invalid(e.to_string()))?,
+            Output::Raw(mut file) => file.flush().map_err(|e| invalid(e.to_string()))?,
+        }
+        Ok(format!(
+            "Saved {} frames to {}",
+            self.frames,
+            self.path.display()
+        ))
+    }
+}
@@ -0,0 +1,156 @@
+// Traces from other emulators, to run the VM in lockstep against them.
+//
+// One line per executed instruction with the state before it, as name=value
+// (or name:value) pairs with hex values, e.g.
+//   pc=0x200 op=00E0 v0=00 v1=00 ... vf=00 i=0000 sp=0 stack=
+// Names are pc, op (or opcode), v0 to vf, i, sp and stack (return addresses
+// separated by commas, innermost last). Fields a line leaves out are not
+// compared, other names are ignored, and so are empty lines and # comments.
+
+use std::{fs, path::Path};
+
+use crate::instruction::disassemble;
+use crate::vm::VM;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReferenceState {
+    // Line in the reference file
+    pub line: usize,
+    pub pc: Option<u16>,
+    pub opcode: Option<u16>,
+    pub registers: [Option<u8>; 16],
+    pub i: Option<u16>,
+    pub sp: Option<usize>,
+    pub stack: Option<Vec<u16>>,
+}
+
+fn parse_hex<T: TryFrom<u32>>(value: &str) -> Option<T> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u32::from_str_radix(digits, 16).ok()?.try_into().ok()
+}
+
+impl ReferenceState {
+    fn parse(line: usize, text: &str) -> Result<Self, String> {
+        let mut state = ReferenceState {
+            line,
+            ..ReferenceState::default()
+        };
+        for field in text.split_whitespace() {
+            let Some((name, value)) = field.split_once(['=', ':']) else {
+                continue;
+            };
+            let invalid = || format!("Reference line {}: invalid value {}", line, field);
+            let name = name.to_ascii_lowercase();
+            match name.as_str() {
+                "pc" => state.pc = Some(parse_hex(value).ok_or_else(invalid)?),
+                "op" | "opcode" => state.opcode = Some(parse_hex(value).ok_or_else(invalid)?),
+                "i" => state.i = Some(parse_hex(value).ok_or_else(invalid)?),
+                "sp" => state.sp = Some(parse_hex(value).ok_or_else(invalid)?),
+                "stack" => {
+                    state.stack = Some(
+                        value
+                            .split(',')
+                            .filter(|address| !address.is_empty())
+                            .map(|address| parse_hex(address).ok_or_else(invalid))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                _ => {
+                    let register = name
+                        .strip_prefix('v')
+                        .filter(|index| index.len() == 1)
+                        .and_then(|index| usize::from_str_radix(index, 16).ok());
+                    if let Some(register) = register {
+                        state.registers[register] = Some(parse_hex(value).ok_or_else(invalid)?);
+                    }
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    // Side by side table of the compared fields, None if the VM matches
+    pub fn diff(&self, vm: &VM) -> Option<String> {
+        let hex = |value: u16| format!("{:#05X}", value);
+        let byte = |value: u8| format!("{:#04X}", value);
+        let stack = |stack: &[u16]| {
+            let addresses: Vec<String> = stack.iter().map(|&address| hex(address)).collect();
+            format!("[{}]", addresses.join(","))
+        };
+        let opcode = vm.opcode_at(vm.pc()).unwrap_or_default();
+        // (name, reference, vm), only for the fields the reference has
+        let mut rows: Vec<(String, String, String)> = vec![];
+        if let Some(pc) = self.pc {
+            rows.push(("PC".to_string(), hex(pc), hex(vm.pc())));
+        }
+        if let Some(reference) = self.opcode {
+            rows.push((
+                "opcode".to_string(),
+                format!("{:04X} {}", reference, disassemble(reference)),
+                format!("{:04X} {}", opcode, disassemble(opcode)),
+            ));
+        }
+        for (index, value) in self.registers.iter().enumerate() {
+            if let Some(value) = value {
+                rows.push((
+                    format!("V{:X}", index),
+                    byte(*value),
+                    byte(vm.registers()[index]),
+                ));
+            }
+        }
+        if let Some(i) = self.i {
+            rows.push(("I".to_string(), hex(i), hex(vm.i())));
+        }
+        if let Some(sp) = self.sp {
+            rows.push((
+                "SP".to_string(),
+                sp.to_string(),
+                vm.stack().len().to_string(),
+            ));
+        }
+        if let Some(reference) = &self.stack {
+            rows.push(("stack".to_string(), stack(reference), stack(vm.stack())));
+        }
+        if rows
+            .iter()
+            .all(|(_, reference, actual)| reference == actual)
+        {
+            return None;
+        }
+        let mut table = format!("{:<8}{:<22}{}\n", "", "reference", "vm");
+        for (name, reference, actual) in rows {
+            let marker = if reference == actual { "" } else { "  <--" };
+            table += &format!("{:<8}{:<22}{}{}\n", name, reference, actual, marker);
+        }
+        Some(table)
+    }
+}
+
+pub struct ReferenceTrace {
+    pub states: Vec<ReferenceState>,
+}
+
+impl ReferenceTrace {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let states = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let line = line.trim();
+                !line.is_empty() && !line.starts_with('#')
+            })
+            .map(|(index, line)| ReferenceState::parse(index + 1, line))
+            .collect::<Result<_, _>>()?;
+        Ok(ReferenceTrace { states })
+    }
+}
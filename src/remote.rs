@@ -0,0 +1,344 @@
+// Remote control over a local socket, one JSON request per line and one JSON
+// response line each, in order. The address is a TCP address such as
+// 127.0.0.1:7000 or, on Unix, unix:<path> for a Unix socket. Clients are
+// served one after the other until one sends quit.
+//
+//   {"cmd":"load_rom","path":"roms/pong.ch8"}   {"ok":true}
+//   {"cmd":"step","count":10}                   {"ok":true,"pc":538}
+//   {"cmd":"registers"}                         {"ok":true,"v":[...],"i":...}
+//   {"cmd":"nope"}                              {"ok":false,"error":"..."}
+//
+// Commands: load_rom (path), reset, step (count instructions), run_frames
+// (count), press and release (key 0-15), registers, memory (address,
+// length), framebuffer (rows of 0 and 1), save_state and load_state (slot,
+// default "default") and quit. Keys stay pressed until released. A client
+// whose connection fails is dropped and the next one served. Once the VM
+// faults, step and run_frames fail until reset, load_rom or load_state.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::database::RomDatabase;
+use crate::input::Input;
+use crate::rom::{Rom, RomSource};
+use crate::settings::Overrides;
+use crate::store::SettingsStore;
+use crate::vm::{RunOptions, State, VM};
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+fn no_rom() -> String {
+    "No ROM loaded, send load_rom first".to_string()
+}
+
+fn halted(vm: &VM) -> Result<(), String> {
+    match vm.fault() {
+        Some(fault) => Err(format!("VM halted: {}", fault)),
+        None => Ok(()),
+    }
+}
+
+fn default_count() -> u64 {
+    1
+}
+
+fn default_slot() -> String {
+    "default".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    LoadRom {
+        path: PathBuf,
+    },
+    Reset,
+    Step {
+        #[serde(default = "default_count")]
+        count: u64,
+    },
+    RunFrames {
+        #[serde(default = "default_count")]
+        count: u64,
+    },
+    Press {
+        key: usize,
+    },
+    Release {
+        key: usize,
+    },
+    Registers,
+    Memory {
+        address: usize,
+        length: usize,
+    },
+    Framebuffer,
+    SaveState {
+        #[serde(default = "default_slot")]
+        slot: String,
+    },
+    LoadState {
+        #[serde(default = "default_slot")]
+        slot: String,
+    },
+    Quit,
+}
+
+pub struct RemoteServer<'a> {
+    database: &'a RomDatabase,
+    overrides: &'a Overrides,
+    store: &'a SettingsStore,
+    options: &'a RunOptions,
+    rom: Option<Rom>,
+    vm: Option<VM>,
+    input: Input,
+    keys: [bool; 16],
+    // Emulated time, for the input timestamps
+    elapsed: Duration,
+    states: HashMap<String, State>,
+}
+
+impl<'a> RemoteServer<'a> {
+    pub fn new(
+        database: &'a RomDatabase,
+        overrides: &'a Overrides,
+        store: &'a SettingsStore,
+        options: &'a RunOptions,
+    ) -> Self {
+        RemoteServer {
+            database,
+            overrides,
+            store,
+            options,
+            rom: None,
+            vm: None,
+            input: Input::new(),
+            keys: [false; 16],
+            elapsed: Duration::ZERO,
+            states: HashMap::new(),
+        }
+    }
+
+    // Boots the ROM and drops the VM of the previous one
+    pub fn load(&mut self, rom: Rom) -> Result<(), String> {
+        self.finish()?;
+        let (vm, settings, _) = VM::boot(
+            &rom,
+            self.database,
+            self.overrides,
+            self.store,
+            self.options,
+        )?;
+        println!("{}", VM::loaded_message(&rom, &settings));
+        self.vm = Some(vm);
+        self.rom = Some(rom);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self.vm.take() {
+            Some(mut vm) => {
+                for message in vm.finish_observers()? {
+                    println!("{}", message);
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn serve(&mut self, address: &str) -> Result<(), String> {
+        let failed = |e: io::Error| format!("Remote control on {} failed: {}", address, e);
+        if let Some(path) = address.strip_prefix("unix:") {
+            return self.serve_unix(path);
+        }
+        let listener = TcpListener::bind(address).map_err(failed)?;
+        println!("Waiting for remote control clients on {}", address);
+        loop {
+            let (stream, _) = listener.accept().map_err(failed)?;
+            let session = stream
+                .try_clone()
+                .and_then(|reader| self.session(BufReader::new(reader), stream));
+            if Self::ended(session) {
+                return self.finish();
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn serve_unix(&mut self, path: &str) -> Result<(), String> {
+        use std::os::unix::net::UnixListener;
+        let failed = |e: io::Error| format!("Remote control on {} failed: {}", path, e);
+        // A socket left behind by an earlier run
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(failed)?;
+        println!("Waiting for remote control clients on {}", path);
+        let served = loop {
+            let (stream, _) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => break Err(failed(e)),
+            };
+            let session = stream
+                .try_clone()
+                .and_then(|reader| self.session(BufReader::new(reader), stream));
+            if Self::ended(session) {
+                break Ok(());
+            }
+        };
+        let _ = std::fs::remove_file(path);
+        served.and_then(|()| self.finish())
+    }
+
+    #[cfg(not(unix))]
+    fn serve_unix(&mut self, _path: &str) -> Result<(), String> {
+        Err("Unix sockets are not supported on this system".to_string())
+    }
+
+    // Whether a session asked to quit. A client that fails, e.g. by sending
+    // invalid UTF-8 or hanging up mid-reply, is only dropped.
+    fn ended(session: io::Result<bool>) -> bool {
+        session.unwrap_or_else(|e| {
+            eprintln!("Remote control client dropped: {}", e);
+            false
+        })
+    }
+
+    // Returns true when the client asked to quit
+    fn session(&mut self, reader: impl BufRead, mut writer: impl Write) -> io::Result<bool> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request = serde_json::from_str::<Request>(&line)
+                .map_err(|e| format!("Invalid request: {}", e));
+            let quit = matches!(request, Ok(Request::Quit));
+            let response = match request.and_then(|request| self.handle(request)) {
+                Ok(Value::Object(mut fields)) => {
+                    fields.insert("ok".to_string(), Value::Bool(true));
+                    Value::Object(fields)
+                }
+                Ok(_) => json!({ "ok": true }),
+                Err(error) => json!({ "ok": false, "error": error }),
+            };
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+            if quit {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn vm(&mut self) -> Result<&mut VM, String> {
+        self.vm.as_mut().ok_or_else(no_rom)
+    }
+
+    fn handle(&mut self, request: Request) -> Result<Value, String> {
+        match request {
+            Request::LoadRom { path } => {
+                self.load(Rom::read(&RomSource::File(path))?)?;
+                // States of the previous ROM
+                self.states.clear();
+                Ok(Value::Null)
+            }
+            // Save states survive a reset
+            Request::Reset => {
+                let rom = self.rom.clone().ok_or_else(no_rom)?;
+                self.load(rom)?;
+                Ok(Value::Null)
+            }
+            // Both stop early on a fault, which stays until a reset or a load
+            Request::Step { count } => {
+                let vm = self.vm.as_mut().ok_or_else(no_rom)?;
+                self.input.update(self.keys, self.elapsed);
+                for _ in 0..count {
+                    if vm.fault().is_some() {
+                        break;
+                    }
+                    if vm.cycle(&self.input) {
+                        self.elapsed += FRAME_DURATION;
+                    }
+                }
+                halted(vm)?;
+                Ok(json!({ "pc": vm.pc() }))
+            }
+            Request::RunFrames { count } => {
+                let vm = self.vm.as_mut().ok_or_else(no_rom)?;
+                for _ in 0..count {
+                    if vm.fault().is_some() {
+                        break;
+                    }
+                    self.input.update(self.keys, self.elapsed);
+                    vm.run_frame(&self.input);
+                    self.elapsed += FRAME_DURATION;
+                }
+                halted(vm)?;
+                Ok(json!({ "pc": vm.pc() }))
+            }
+            Request::Press { key } | Request::Release { key } if key >= self.keys.len() => {
+                Err(format!("Invalid key {}, keys are 0 to 15", key))
+            }
+            Request::Press { key } => {
+                self.keys[key] = true;
+                Ok(Value::Null)
+            }
+            Request::Release { key } => {
+                self.keys[key] = false;
+                Ok(Value::Null)
+            }
+            Request::Registers => {
+                let vm = self.vm()?;
+                let (delay, sound) = vm.timers();
+                Ok(json!({
+                    "v": vm.registers(),
+                    "i": vm.i(),
+                    "pc": vm.pc(),
+                    "stack": vm.stack(),
+                    "dt": delay,
+                    "st": sound,
+                }))
+            }
+            Request::Memory { address, length } => {
+                let memory = self.vm()?.memory();
+                let data = memory
+                    .get(address..address.saturating_add(length))
+                    .ok_or_else(|| format!("Memory is {} bytes", memory.len()))?;
+                Ok(json!({ "data": data }))
+            }
+            Request::Framebuffer => {
+                let rows: Vec<String> = self
+                    .vm()?
+                    .display()
+                    .iter()
+                    .map(|row| row.iter().map(|&pixel| (b'0' + pixel) as char).collect())
+                    .collect();
+                Ok(json!({ "width": CHIP8_WIDTH, "height": CHIP8_HEIGHT, "rows": rows }))
+            }
+            Request::SaveState { slot } => {
+                let state = self.vm()?.save_state();
+                self.states.insert(slot, state);
+                Ok(Value::Null)
+            }
+            Request::LoadState { slot } => {
+                let state = self
+                    .states
+                    .get(&slot)
+                    .ok_or_else(|| format!("No state saved in slot {}", slot))?
+                    .clone();
+                self.vm()?.load_state(&state);
+                Ok(Value::Null)
+            }
+            Request::Quit => Ok(Value::Null),
+        }
+    }
+}
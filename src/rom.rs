@@ -0,0 +1,283 @@
+// ROM loading from files, stdin or raw bytes. Gzip files and zip archives
+// holding a single ROM are unpacked, Octo cartridges are assembled.
+
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::cartridge::{self, Cartridge};
+use crate::constants::{MEMORY_SIZE, PROGRAM_START};
+use crate::settings::Overrides;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+// Unpacked gzip and zip contents, larger than programs as they can be Octo
+// cartridges, small enough that a compression bomb cannot exhaust memory
+const UNPACKED_LIMIT: u64 = 1 << 20;
+
+// Reads at most UNPACKED_LIMIT bytes, more is an error
+fn read_unpacked(reader: impl Read) -> Result<Vec<u8>, String> {
+    let mut unpacked = vec![];
+    reader
+        .take(UNPACKED_LIMIT + 1)
+        .read_to_end(&mut unpacked)
+        .map_err(|e| e.to_string())?;
+    if unpacked.len() as u64 > UNPACKED_LIMIT {
+        return Err(format!("more than {} bytes unpacked", UNPACKED_LIMIT));
+    }
+    Ok(unpacked)
+}
+
+pub enum RomSource {
+    File(PathBuf),
+    Stdin,
+}
+
+impl RomSource {
+    // "-" reads from stdin
+    pub fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            RomSource::Stdin
+        } else {
+            RomSource::File(PathBuf::from(arg))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rom {
+    // File name of the program itself, e.g. "pong.ch8" for pong.ch8.gz
+    pub name: String,
+    pub bytes: Vec<u8>,
+    // Settings shipped with the program, e.g. the options of an Octo cartridge
+    pub settings: Overrides,
+}
+
+impl Rom {
+    pub fn read(source: &RomSource) -> Result<Self, String> {
+        match source {
+            RomSource::File(path) => {
+                let bytes =
+                    fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string());
+                Self::from_bytes(&name, bytes)
+            }
+            RomSource::Stdin => Self::from_reader("stdin", io::stdin()),
+        }
+    }
+
+    pub fn from_reader(name: &str, mut reader: impl Read) -> Result<Self, String> {
+        let mut bytes = vec![];
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Cannot read ROM from {}: {}", name, e))?;
+        Self::from_bytes(name, bytes)
+    }
+
+    pub fn from_bytes(name: &str, bytes: Vec<u8>) -> Result<Self, String> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            let unpacked = read_unpacked(GzDecoder::new(bytes.as_slice()))
+                .map_err(|e| format!("Cannot decompress {}: {}", name, e))?;
+            let name = name
+                .strip_suffix(".gz")
+                .or_else(|| name.strip_suffix(".gzip"))
+                .unwrap_or(name);
+            Self::from_bytes(name, unpacked)
+        } else if bytes.starts_with(&ZIP_MAGIC) {
+            Self::from_zip(name, bytes)
+        } else if cartridge::is_cartridge(&bytes) {
+            let cartridge =
+                Cartridge::decode(&bytes).map_err(|e| format!("Cannot load {}: {}", name, e))?;
+            Ok(Rom {
+                name: name.to_string(),
+                bytes: cartridge.program,
+                settings: cartridge.settings,
+            })
+        } else if bytes.is_empty() {
+            Err(format!("{} is empty", name))
+        } else {
+            Ok(Rom {
+                name: name.to_string(),
+                bytes,
+                settings: Overrides::default(),
+            })
+        }
+    }
+
+    fn from_zip(name: &str, bytes: Vec<u8>) -> Result<Self, String> {
+        let invalid = |e: zip::result::ZipError| format!("Invalid zip archive {}: {}", name, e);
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(invalid)?;
+        let files: Vec<usize> = (0..archive.len())
+            .filter(|&index| {
+                archive
+                    .by_index(index)
+                    .map(|entry| entry.is_file())
+                    .unwrap_or(false)
+            })
+            .collect();
+        let [index] = files[..] else {
+            return Err(format!(
+                "{} should contain exactly one ROM, found {} files",
+                name,
+                files.len()
+            ));
+        };
+        let entry = archive.by_index(index).map_err(invalid)?;
+        let entry_path = entry.name().map_err(invalid)?.into_owned();
+        let entry_name = Path::new(&entry_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let unpacked = read_unpacked(entry)
+            .map_err(|e| format!("Cannot extract {} from {}: {}", entry_name, name, e))?;
+        Self::from_bytes(&entry_name, unpacked)
+    }
+
+    pub fn path(&self) -> &Path {
+        Path::new(&self.name)
+    }
+
+    // Programs are loaded at 0x200 and have to fit in the VM's 4K, also
+    // those of platforms with more memory such as XO-CHIP
+    pub fn check_size(&self) -> Result<(), String> {
+        let available = MEMORY_SIZE - PROGRAM_START;
+        if self.bytes.len() > available {
+            return Err(format!(
+                "{} is {} bytes, programs can be at most {} bytes",
+                self.name,
+                self.bytes.len(),
+                available
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    const PROGRAM: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .add_directory("games/", SimpleFileOptions::default())
+            .unwrap();
+        for (name, bytes) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn raw_programs() {
+        let rom = Rom::from_bytes("pong.ch8", PROGRAM.to_vec()).unwrap();
+        assert_eq!(
+            (rom.name.as_str(), rom.bytes),
+            ("pong.ch8", PROGRAM.to_vec())
+        );
+        assert_eq!(rom.settings, Overrides::default());
+        assert!(Rom::from_bytes("empty.ch8", vec![]).is_err());
+    }
+
+    #[test]
+    fn readers_like_stdin() {
+        let rom = Rom::from_reader("stdin", Cursor::new(gzip(&PROGRAM))).unwrap();
+        assert_eq!((rom.name.as_str(), rom.bytes), ("stdin", PROGRAM.to_vec()));
+    }
+
+    #[test]
+    fn gzip_files() {
+        let rom = Rom::from_bytes("pong.ch8.gz", gzip(&PROGRAM)).unwrap();
+        assert_eq!(
+            (rom.name.as_str(), rom.bytes),
+            ("pong.ch8", PROGRAM.to_vec())
+        );
+        // Truncated streams are errors, not short programs
+        let packed = gzip(&[0xAB; 1000]);
+        assert!(Rom::from_bytes("cut.ch8.gz", packed[..packed.len() / 2].to_vec()).is_err());
+    }
+
+    #[test]
+    fn zip_archives() {
+        let rom = Rom::from_bytes("pong.zip", zip(&[("games/pong.ch8", &PROGRAM)])).unwrap();
+        assert_eq!(
+            (rom.name.as_str(), rom.bytes),
+            ("pong.ch8", PROGRAM.to_vec())
+        );
+        // Archives inside archives are unpacked too
+        let nested = zip(&[("pong.ch8.gz", &gzip(&PROGRAM))]);
+        let rom = Rom::from_bytes("pong.zip", nested).unwrap();
+        assert_eq!(
+            (rom.name.as_str(), rom.bytes),
+            ("pong.ch8", PROGRAM.to_vec())
+        );
+        let two = zip(&[("pong.ch8", &PROGRAM), ("readme.txt", b"Pong")]);
+        assert_eq!(
+            Rom::from_bytes("two.zip", two).err(),
+            Some("two.zip should contain exactly one ROM, found 2 files".to_string())
+        );
+    }
+
+    #[test]
+    fn unpacking_stops_at_the_limit() {
+        let limit = UNPACKED_LIMIT as usize;
+        let rom = Rom::from_bytes("big.gz", gzip(&vec![0xAB; limit])).unwrap();
+        assert_eq!(rom.bytes.len(), limit);
+        let bomb = gzip(&vec![0xAB; limit + 1]);
+        assert_eq!(
+            Rom::from_bytes("bomb.gz", bomb).err(),
+            Some(format!(
+                "Cannot decompress bomb.gz: more than {} bytes unpacked",
+                limit
+            ))
+        );
+        let bomb = zip(&[("bomb.ch8", &vec![0xAB; limit + 1])]);
+        assert_eq!(
+            Rom::from_bytes("bomb.zip", bomb).err(),
+            Some(format!(
+                "Cannot extract bomb.ch8 from bomb.zip: more than {} bytes unpacked",
+                limit
+            ))
+        );
+    }
+
+    #[test]
+    fn programs_fit_after_0x200() {
+        let available = MEMORY_SIZE - PROGRAM_START;
+        let rom = Rom::from_bytes("full.ch8", vec![0xAB; available]).unwrap();
+        assert!(rom.check_size().is_ok());
+        let rom = Rom::from_bytes("large.ch8", vec![0xAB; available + 1]).unwrap();
+        assert_eq!(
+            rom.check_size().err(),
+            Some(format!(
+                "large.ch8 is {} bytes, programs can be at most {} bytes",
+                available + 1,
+                available
+            ))
+        );
+    }
+}
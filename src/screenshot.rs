@@ -0,0 +1,64 @@
+// PNG screenshots of the display, in the active palette
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::palette::Palette;
+
+// Image bytes, row by row, each display pixel repeated scale x scale times
+pub fn scaled<const N: usize>(
+    pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    scale: usize,
+    pixel: impl Fn(u8) -> [u8; N],
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(CHIP8_WIDTH * CHIP8_HEIGHT * scale * scale * N);
+    for row in pixels {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|&value| pixel(value).repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+    data
+}
+
+// e.g. pong-000120.png for frame 120 of pong.ch8, in the current directory
+pub fn file_name(rom_name: &str, frame: u64, extension: &str) -> PathBuf {
+    let stem = Path::new(rom_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+    PathBuf::from(format!("{}-{:06}.{}", stem, frame, extension))
+}
+
+// Each display pixel becomes scale x scale image pixels, 1 is native resolution
+pub fn save_png(
+    path: &Path,
+    pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    palette: &Palette,
+    scale: u32,
+) -> Result<(), String> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+    let data = scaled(pixels, scale, |pixel| palette.color(pixel));
+    write_png(path, width, height, &data)
+}
+
+// RGB image bytes, row by row
+pub fn write_png(path: &Path, width: usize, height: usize, data: &[u8]) -> Result<(), String> {
+    let invalid = |e: png::EncodingError| format!("Cannot write {}: {}", path.display(), e);
+    let file =
+        File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(invalid)?;
+    writer.write_image_data(data).map_err(invalid)?;
+    writer.finish().map_err(invalid)
+}
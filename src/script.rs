@@ -0,0 +1,299 @@
+// Rhai scripts hooked into a run, for autoplay bots and ROM assertions.
+//
+// The script runs once when the ROM is loaded and registers its hooks there:
+//
+//   on_frame(|frame| { ... })          after every frame
+//   on_pc(0x21A, |pc| { ... })          before the instruction at an address
+//   on_write(0x2F2, |address, value| { ... })
+//   on_write(0x2F2, 0x2F4, |address, value| { ... })
+//                                       after FX33 or FX55 wrote an address
+//
+// Hooks can read and change the machine with reg(x), set_reg(x, value), i(),
+// set_i(value), pc(), set_pc(value), peek(address), poke(address, value),
+// dt() and st(), hold keys with press(key) and release(key), show a line of
+// text over the display with text(line) (cleared every frame) and end the
+// run with quit(). throw stops the run with an error. print and debug output
+// goes wherever the frontend shows messages.
+//
+// Closures keep the variables they capture between calls:
+//
+//   let misses = 0;
+//   on_pc(0x2A2, |pc| { misses += 1; if misses == 3 { throw "lost"; } });
+
+use std::{
+    cell::RefCell, collections::HashMap, fs, ops::RangeInclusive, path::Path, path::PathBuf, rc::Rc,
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST, INT};
+
+use crate::constants::MEMORY_SIZE;
+use crate::input::Input;
+use crate::instruction::{Access, Instruction};
+use crate::vm::VM;
+
+type Outcome<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    pc: HashMap<u16, Vec<FnPtr>>,
+    write: Vec<(RangeInclusive<u16>, FnPtr)>,
+}
+
+// What the registered functions work on. The VM is swapped in for the
+// duration of each hook.
+#[derive(Default)]
+struct Shared {
+    vm: VM,
+    keys: [bool; 16],
+    text: Vec<String>,
+    // print and debug lines not taken yet
+    output: Vec<String>,
+    quit: bool,
+    hooks: Hooks,
+}
+
+fn index(value: INT, count: usize, what: &str) -> Outcome<usize> {
+    usize::try_from(value)
+        .ok()
+        .filter(|&index| index < count)
+        .ok_or_else(|| format!("Invalid {} {}, expected 0 to {}", what, value, count - 1).into())
+}
+
+fn byte(value: INT) -> Outcome<u8> {
+    u8::try_from(value).map_err(|_| format!("Invalid byte {}, expected 0 to 255", value).into())
+}
+
+fn address(value: INT) -> Outcome<u16> {
+    index(value, MEMORY_SIZE, "address").map(|address| address as u16)
+}
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Script {
+    // Compiles the script and runs it once against the freshly loaded VM
+    pub fn load(path: &Path, vm: &mut VM) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read script {}: {}", path.display(), e))?;
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let engine = Self::engine(&shared);
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| format!("Script {}: {}", path.display(), e))?;
+        let script = Script {
+            path: path.to_path_buf(),
+            engine,
+            ast,
+            shared,
+        };
+        script.with_vm(vm, |script| script.engine.run_ast(&script.ast))?;
+        Ok(script)
+    }
+
+    fn engine(shared: &Rc<RefCell<Shared>>) -> Engine {
+        let mut engine = Engine::new();
+        let state = shared.clone();
+        engine.on_print(move |line| state.borrow_mut().output.push(line.to_string()));
+        let state = shared.clone();
+        engine.on_debug(move |line, _, position| {
+            let line = format!("{} {}", position, line);
+            state.borrow_mut().output.push(line)
+        });
+        let state = shared.clone();
+        engine.register_fn("reg", move |x: INT| -> Outcome<INT> {
+            Ok(state.borrow().vm.registers()[index(x, 16, "register")?] as INT)
+        });
+        let state = shared.clone();
+        engine.register_fn("set_reg", move |x: INT, value: INT| -> Outcome<()> {
+            let (x, value) = (index(x, 16, "register")?, byte(value)?);
+            state.borrow_mut().vm.set_register(x, value);
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("i", move || state.borrow().vm.i() as INT);
+        let state = shared.clone();
+        engine.register_fn("set_i", move |value: INT| -> Outcome<()> {
+            let value = u16::try_from(value).map_err(|_| format!("Invalid I {}", value))?;
+            state.borrow_mut().vm.set_i_register(value);
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("pc", move || state.borrow().vm.pc() as INT);
+        let state = shared.clone();
+        engine.register_fn("set_pc", move |value: INT| -> Outcome<()> {
+            state.borrow_mut().vm.set_pc(address(value)?);
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("peek", move |at: INT| -> Outcome<INT> {
+            Ok(state.borrow().vm.memory()[address(at)? as usize] as INT)
+        });
+        let state = shared.clone();
+        engine.register_fn("poke", move |at: INT, value: INT| -> Outcome<()> {
+            let (at, value) = (address(at)?, byte(value)?);
+            state.borrow_mut().vm.set_byte(at as usize, value);
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("dt", move || state.borrow().vm.timers().0 as INT);
+        let state = shared.clone();
+        engine.register_fn("st", move || state.borrow().vm.timers().1 as INT);
+        let state = shared.clone();
+        engine.register_fn("press", move |key: INT| -> Outcome<()> {
+            state.borrow_mut().keys[index(key, 16, "key")?] = true;
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("release", move |key: INT| -> Outcome<()> {
+            state.borrow_mut().keys[index(key, 16, "key")?] = false;
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("text", move |line: &str| {
+            state.borrow_mut().text.push(line.to_string())
+        });
+        let state = shared.clone();
+        engine.register_fn("quit", move || state.borrow_mut().quit = true);
+        let state = shared.clone();
+        engine.register_fn("on_frame", move |hook: FnPtr| {
+            state.borrow_mut().hooks.frame.push(hook)
+        });
+        let state = shared.clone();
+        engine.register_fn("on_pc", move |at: INT, hook: FnPtr| -> Outcome<()> {
+            let at = address(at)?;
+            state
+                .borrow_mut()
+                .hooks
+                .pc
+                .entry(at)
+                .or_default()
+                .push(hook);
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn("on_write", move |at: INT, hook: FnPtr| -> Outcome<()> {
+            let at = address(at)?;
+            state.borrow_mut().hooks.write.push((at..=at, hook));
+            Ok(())
+        });
+        let state = shared.clone();
+        engine.register_fn(
+            "on_write",
+            move |start: INT, end: INT, hook: FnPtr| -> Outcome<()> {
+                let range = address(start)?..=address(end)?;
+                state.borrow_mut().hooks.write.push((range, hook));
+                Ok(())
+            },
+        );
+        engine
+    }
+
+    // Lends the VM to the registered functions while the script runs
+    fn with_vm<T>(&self, vm: &mut VM, run: impl FnOnce(&Self) -> Outcome<T>) -> Result<T, String> {
+        std::mem::swap(vm, &mut self.shared.borrow_mut().vm);
+        let result = run(self);
+        std::mem::swap(vm, &mut self.shared.borrow_mut().vm);
+        result.map_err(|e| format!("Script {}: {}", self.path.display(), e))
+    }
+
+    fn call(
+        &self,
+        vm: &mut VM,
+        hooks: &[FnPtr],
+        args: impl FuncArgs + Clone,
+    ) -> Result<(), String> {
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        self.with_vm(vm, |script| {
+            hooks.iter().try_for_each(|hook| {
+                hook.call::<Dynamic>(&script.engine, &script.ast, args.clone())
+                    .map(drop)
+            })
+        })
+    }
+
+    // One frame of the VM with the hooks. Runs cycle by cycle only when the
+    // script watches addresses or writes.
+    pub fn run_frame(&mut self, vm: &mut VM, input: &Input, frame: u64) -> Result<(), String> {
+        let (watches_pc, watches_writes) = {
+            let mut shared = self.shared.borrow_mut();
+            shared.text.clear();
+            (!shared.hooks.pc.is_empty(), !shared.hooks.write.is_empty())
+        };
+        if watches_pc || watches_writes {
+            loop {
+                // Not while FX0A blocks, the PC already points past it, nor
+                // once the VM faulted and the PC stays on the fault
+                if watches_pc && !vm.waiting_for_key() && vm.fault().is_none() {
+                    let hooks = self.shared.borrow().hooks.pc.get(&vm.pc()).cloned();
+                    if let Some(hooks) = hooks {
+                        self.call(vm, &hooks, (vm.pc() as INT,))?;
+                    }
+                }
+                let (pc, i) = (vm.pc(), vm.i());
+                let opcode = vm.opcode_at(pc);
+                let ended = vm.cycle(input);
+                // FX33 and FX55 ran if the PC moved on by one instruction
+                if watches_writes && vm.pc() == pc.wrapping_add(2) {
+                    self.written(vm, opcode, i)?;
+                }
+                if ended {
+                    break;
+                }
+            }
+        } else {
+            vm.run_frame(input);
+        }
+        let hooks = self.shared.borrow().hooks.frame.clone();
+        self.call(vm, &hooks, (frame as INT,))
+    }
+
+    fn written(&self, vm: &mut VM, opcode: Option<u16>, i: u16) -> Result<(), String> {
+        let accesses = opcode
+            .and_then(Instruction::from_u16)
+            .and_then(|instruction| instruction.memory_accesses(i))
+            .filter(|accesses| accesses.kind == Access::Write);
+        let Some(accesses) = accesses else {
+            return Ok(());
+        };
+        for &address in accesses.addresses() {
+            let value = vm.memory()[address as usize];
+            let hooks: Vec<FnPtr> = self
+                .shared
+                .borrow()
+                .hooks
+                .write
+                .iter()
+                .filter(|(range, _)| range.contains(&address))
+                .map(|(_, hook)| hook.clone())
+                .collect();
+            self.call(vm, &hooks, (address as INT, value as INT))?;
+        }
+        Ok(())
+    }
+
+    // Keys held by the script, added to the player's
+    pub fn keys(&self) -> [bool; 16] {
+        self.shared.borrow().keys
+    }
+
+    // Lines shown over the display, from the last frame
+    pub fn text(&self) -> Vec<String> {
+        self.shared.borrow().text.clone()
+    }
+
+    // print and debug lines since the last call
+    pub fn take_output(&self) -> Vec<String> {
+        std::mem::take(&mut self.shared.borrow_mut().output)
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.shared.borrow().quit
+    }
+}
@@ -0,0 +1,378 @@
+// SDL2 window frontend, with keyboard and game controller input
+
+use std::{collections::HashMap, time::Duration};
+
+use sdl2::{
+    controller::{Button, GameController},
+    event::Event,
+    keyboard::{Keycode, Scancode},
+    pixels::{self, Color},
+    rect::Rect,
+    render::Canvas,
+    video::Window,
+    EventPump, GameControllerSubsystem, TimerSubsystem,
+};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::input::{KeyEvent, KeyEventKind, KeyPoll};
+use crate::keymap::{Bindings, KeyMode};
+use crate::palette::Palette;
+use crate::renderer::{Command, Renderer};
+use crate::text::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const SCALE_FACTOR: u32 = 20;
+pub const WINDOW_WIDTH: u32 = CHIP8_WIDTH as u32 * SCALE_FACTOR;
+pub const WINDOW_HEIGHT: u32 = CHIP8_HEIGHT as u32 * SCALE_FACTOR;
+const STATUS_SCALE: u32 = 4;
+const STATUS_MARGIN: i32 = 8;
+const STATUS_COLOR: Color = Color::RGB(255, 200, 0);
+// The window grows by this much to the right while the overlay is shown
+const OVERLAY_WIDTH: u32 = 480;
+const OVERLAY_SCALE: u32 = 2;
+const OVERLAY_MARGIN: i32 = 12;
+const OVERLAY_COLOR: Color = Color::RGB(200, 200, 200);
+
+// Key names resolved to SDL codes once, at startup
+enum KeyBindings {
+    Keycode(HashMap<Keycode, usize>),
+    Scancode(HashMap<Scancode, usize>),
+}
+
+impl KeyBindings {
+    fn resolve(bindings: &Bindings) -> Result<KeyBindings, String> {
+        let unknown_key = |name: &String| format!("Unknown key name in bindings: {}", name);
+        match bindings.mode {
+            KeyMode::Keycode => bindings
+                .keys
+                .iter()
+                .map(|(name, &index)| {
+                    Keycode::from_name(name)
+                        .map(|key| (key, index as usize))
+                        .ok_or_else(|| unknown_key(name))
+                })
+                .collect::<Result<_, _>>()
+                .map(KeyBindings::Keycode),
+            KeyMode::Scancode => bindings
+                .keys
+                .iter()
+                .map(|(name, &index)| {
+                    Scancode::from_name(name)
+                        .map(|key| (key, index as usize))
+                        .ok_or_else(|| unknown_key(name))
+                })
+                .collect::<Result<_, _>>()
+                .map(KeyBindings::Scancode),
+        }
+    }
+
+    fn key_index(&self, scancode: Scancode) -> Option<usize> {
+        match self {
+            KeyBindings::Keycode(keys) => Keycode::from_scancode(scancode)
+                .and_then(|key| keys.get(&key))
+                .copied(),
+            KeyBindings::Scancode(keys) => keys.get(&scancode).copied(),
+        }
+    }
+}
+
+fn resolve_buttons(bindings: &Bindings) -> Result<Vec<(Button, usize)>, String> {
+    bindings
+        .buttons
+        .iter()
+        .map(|(name, &index)| {
+            Button::from_string(name)
+                .map(|button| (button, index as usize))
+                .ok_or_else(|| format!("Unknown controller button in bindings: {}", name))
+        })
+        .collect()
+}
+
+// Fails on key and button names SDL does not know, e.g. before saving them
+pub fn check_bindings(bindings: &Bindings) -> Result<(), String> {
+    KeyBindings::resolve(bindings)?;
+    resolve_buttons(bindings).map(|_| ())
+}
+
+// SDL times events in milliseconds since it started
+fn key_event(key: usize, kind: KeyEventKind, timestamp: u32) -> KeyEvent {
+    KeyEvent {
+        key,
+        kind,
+        timestamp: Duration::from_millis(timestamp as u64),
+    }
+}
+
+pub struct SDLWrapper {
+    canvas: Canvas<Window>,
+    event_handler: EventPump,
+    timer: TimerSubsystem,
+    key_bindings: KeyBindings,
+    controller_subsystem: GameControllerSubsystem,
+    // Opened controllers by joystick instance id
+    controllers: HashMap<u32, GameController>,
+    button_bindings: Vec<(Button, usize)>,
+    palette: Palette,
+    status: Option<String>,
+    overlay: Option<Vec<String>>,
+}
+
+fn find_sdl_gl_driver() -> Option<u32> {
+    for (index, item) in sdl2::render::drivers().enumerate() {
+        if item.name == "opengl" {
+            return Some(index as u32);
+        }
+    }
+    None
+}
+
+impl SDLWrapper {
+    // Nothing is bound to the keypad until set_bindings is called
+    pub fn initialize_sdl_renderer() -> Result<SDLWrapper, String> {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+        let window = video_subsystem
+            .window("Chip-8", WINDOW_WIDTH, WINDOW_HEIGHT)
+            .opengl() // this line DOES NOT enable opengl, but allows you to create/get an OpenGL context from your window.
+            .build()
+            .unwrap();
+        let mut canvas = window
+            .into_canvas()
+            .index(find_sdl_gl_driver().unwrap())
+            .build()
+            .unwrap();
+        // Already connected controllers are reported as ControllerDeviceAdded events
+        let controller_subsystem = sdl_context.game_controller()?;
+        let event_pump = sdl_context.event_pump()?;
+        let timer = sdl_context.timer()?;
+
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        Ok(SDLWrapper {
+            canvas,
+            event_handler: event_pump,
+            timer,
+            key_bindings: KeyBindings::Keycode(HashMap::new()),
+            controller_subsystem,
+            controllers: HashMap::new(),
+            button_bindings: vec![],
+            palette: Palette::default(),
+            status: None,
+            overlay: None,
+        })
+    }
+
+    // x and y are in window pixels, each glyph pixel is scale x scale
+    pub fn draw_text(&mut self, x: i32, y: i32, content: &str, scale: u32, color: Color) {
+        self.canvas.set_draw_color(color);
+        for (index, c) in content.chars().enumerate() {
+            let glyph_x = x + (index * (GLYPH_WIDTH + 1)) as i32 * scale as i32;
+            for (row, bits) in text::glyph(c).into_iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if (bits >> (GLYPH_WIDTH - 1 - column)) & 1 == 1 {
+                        let _ = self.canvas.fill_rect(Rect::new(
+                            glyph_x + (column as u32 * scale) as i32,
+                            y + (row as u32 * scale) as i32,
+                            scale,
+                            scale,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn text_line_height(scale: u32) -> i32 {
+        ((GLYPH_HEIGHT + 1) as u32 * scale) as i32
+    }
+
+    pub fn present(&mut self) {
+        self.canvas.present();
+    }
+
+    fn color(&self, value: u8) -> pixels::Color {
+        let [r, g, b] = self.palette.color(value);
+        pixels::Color::RGB(r, g, b)
+    }
+}
+
+impl Renderer for SDLWrapper {
+    fn clear_screen(&mut self) {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear()
+    }
+
+    fn handle_event(&mut self) -> (KeyPoll, Vec<Command>) {
+        let mut commands = vec![];
+        let mut events = vec![];
+        // Shown once the events are handled, the event pump is borrowed until then
+        let mut messages = vec![];
+        for event in self.event_handler.poll_iter() {
+            match event {
+                Event::Quit { .. } => commands.push(Command::Quit),
+                Event::KeyDown {
+                    timestamp,
+                    keycode,
+                    scancode,
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(key) = scancode.and_then(|code| self.key_bindings.key_index(code)) {
+                        events.push(key_event(key, KeyEventKind::Pressed, timestamp));
+                    }
+                    let command = keycode.and_then(|keycode| match keycode {
+                        Keycode::Escape => Some(Command::Quit),
+                        Keycode::F1 => Some(Command::Launcher),
+                        Keycode::F3 => Some(Command::Overlay),
+                        Keycode::F5 => Some(Command::Pause),
+                        Keycode::F6 => Some(Command::FrameAdvance),
+                        Keycode::F7 => Some(Command::Step),
+                        Keycode::F8 => Some(Command::Faster),
+                        Keycode::F9 => Some(Command::Slower),
+                        Keycode::F10 => Some(Command::Record),
+                        Keycode::F12 => Some(Command::Screenshot),
+                        Keycode::Up => Some(Command::Up),
+                        Keycode::Down => Some(Command::Down),
+                        Keycode::Return => Some(Command::Select),
+                        _ => None,
+                    });
+                    commands.extend(command);
+                }
+                Event::KeyUp {
+                    timestamp,
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    if let Some(key) = self.key_bindings.key_index(scancode) {
+                        events.push(key_event(key, KeyEventKind::Released, timestamp));
+                    }
+                }
+                Event::ControllerButtonDown {
+                    timestamp, button, ..
+                } => events.extend(
+                    self.button_bindings
+                        .iter()
+                        .filter(|&&(bound, _)| bound == button)
+                        .map(|&(_, key)| key_event(key, KeyEventKind::Pressed, timestamp)),
+                ),
+                Event::ControllerButtonUp {
+                    timestamp, button, ..
+                } => events.extend(
+                    self.button_bindings
+                        .iter()
+                        .filter(|&&(bound, _)| bound == button)
+                        .map(|&(_, key)| key_event(key, KeyEventKind::Released, timestamp)),
+                ),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match self.controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            messages.push(format!("Controller connected: {}", controller.name()));
+                            self.controllers
+                                .insert(controller.instance_id(), controller);
+                        }
+                        Err(e) => messages.push(format!("Cannot open controller {}: {}", which, e)),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controller) = self.controllers.remove(&which) {
+                        messages.push(format!("Controller disconnected: {}", controller.name()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for message in messages {
+            self.message(&message);
+        }
+        let mut keys = [false; 16];
+
+        self.event_handler
+            .keyboard_state()
+            .pressed_scancodes()
+            .filter_map(|scancode| self.key_bindings.key_index(scancode))
+            .for_each(|key_index| keys[key_index] = true);
+        for controller in self.controllers.values() {
+            for &(button, key_index) in &self.button_bindings {
+                if controller.button(button) {
+                    keys[key_index] = true;
+                }
+            }
+        }
+        let poll = KeyPoll {
+            events,
+            keys,
+            timestamp: Duration::from_millis(self.timer.ticks() as u64),
+        };
+        (poll, commands)
+    }
+
+    fn draw(&mut self, pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, &col) in row.iter().enumerate() {
+                let x = (x as u32) * SCALE_FACTOR;
+                let y = (y as u32) * SCALE_FACTOR;
+
+                self.canvas.set_draw_color(self.color(col));
+                let _ = self.canvas.fill_rect(Rect::new(
+                    x as i32,
+                    y as i32,
+                    SCALE_FACTOR,
+                    SCALE_FACTOR,
+                ));
+            }
+        }
+        if let Some(lines) = self.overlay.take() {
+            self.canvas.set_draw_color(Color::BLACK);
+            let _ = self.canvas.fill_rect(Rect::new(
+                WINDOW_WIDTH as i32,
+                0,
+                OVERLAY_WIDTH,
+                WINDOW_HEIGHT,
+            ));
+            let line_height = Self::text_line_height(OVERLAY_SCALE);
+            for (index, line) in lines.iter().enumerate() {
+                let y = OVERLAY_MARGIN + index as i32 * line_height;
+                let x = WINDOW_WIDTH as i32 + OVERLAY_MARGIN;
+                self.draw_text(x, y, line, OVERLAY_SCALE, OVERLAY_COLOR);
+            }
+            self.overlay = Some(lines);
+        }
+        if let Some(status) = self.status.clone() {
+            self.draw_text(
+                STATUS_MARGIN,
+                STATUS_MARGIN,
+                &status,
+                STATUS_SCALE,
+                STATUS_COLOR,
+            );
+        }
+        self.canvas.present();
+    }
+
+    fn set_bindings(&mut self, bindings: &Bindings) -> Result<(), String> {
+        self.key_bindings = KeyBindings::resolve(bindings)?;
+        self.button_bindings = resolve_buttons(bindings)?;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+    }
+
+    fn message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn set_overlay(&mut self, lines: Option<Vec<String>>) {
+        if lines.is_some() != self.overlay.is_some() {
+            let width = match lines {
+                Some(_) => WINDOW_WIDTH + OVERLAY_WIDTH,
+                None => WINDOW_WIDTH,
+            };
+            let _ = self.canvas.window_mut().set_size(width, WINDOW_HEIGHT);
+        }
+        self.overlay = lines;
+    }
+}
@@ -0,0 +1,152 @@
+// Settings for running one ROM, resolved from the platform defaults, the ROM
+// database and the user's overrides, in that order
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::RomDatabase;
+use crate::keymap::Bindings;
+use crate::palette::{parse_color, Palette};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+// Anything the user forces, on the command line or in the per-ROM settings store
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Overrides {
+    // chip-8-database platform id, e.g. "modernChip8"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Palette>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub quirks: BTreeMap<String, bool>,
+    // Added on top of the key map bindings, SDL key and button names to keypad indices
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, u8>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub buttons: BTreeMap<String, u8>,
+}
+
+fn merge<V: Copy>(base: &BTreeMap<String, V>, top: &BTreeMap<String, V>) -> BTreeMap<String, V> {
+    let mut merged = base.clone();
+    merged.extend(top.iter().map(|(name, &value)| (name.clone(), value)));
+    merged
+}
+
+impl Overrides {
+    // Values set here win over the ones in base
+    pub fn layered_over(&self, base: &Overrides) -> Overrides {
+        Overrides {
+            platform: self.platform.clone().or_else(|| base.platform.clone()),
+            tickrate: self.tickrate.or(base.tickrate),
+            palette: self.palette.or(base.palette),
+            quirks: merge(&base.quirks, &self.quirks),
+            keys: merge(&base.keys, &self.keys),
+            buttons: merge(&base.buttons, &self.buttons),
+        }
+    }
+
+    pub fn apply_bindings(&self, bindings: &mut Bindings) {
+        bindings
+            .keys
+            .extend(self.keys.iter().map(|(name, &index)| (name.clone(), index)));
+        bindings.buttons.extend(
+            self.buttons
+                .iter()
+                .map(|(name, &index)| (name.clone(), index)),
+        );
+    }
+
+    // "NAME=INDEX" with a hexadecimal keypad index, as given on the command line
+    pub fn parse_binding(assignment: &str) -> Result<(String, u8), String> {
+        let (name, index) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME=INDEX, got {}", assignment))?;
+        let index = u8::from_str_radix(index.trim_start_matches("0x"), 16)
+            .ok()
+            .filter(|&index| index <= 0xF)
+            .ok_or_else(|| format!("Invalid keypad index {}, expected 0 to F", index))?;
+        Ok((name.to_string(), index))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RomSettings {
+    pub sha1: String,
+    pub title: Option<String>,
+    pub platform: Platform,
+    pub platform_id: String,
+    pub quirks: Quirks,
+    // Instructions executed per 60Hz frame
+    pub tickrate: u32,
+    pub palette: Palette,
+    // chip-8-database key names ("up", "a"...) to keypad indices
+    pub key_hints: HashMap<String, u8>,
+}
+
+impl RomSettings {
+    pub fn resolve(
+        database: &RomDatabase,
+        rom_path: &Path,
+        sha1: String,
+        overrides: &Overrides,
+    ) -> Result<Self, String> {
+        let info = database.lookup(&sha1);
+        let platform_id = match (&overrides.platform, &info) {
+            (Some(id), _) => id.clone(),
+            (None, Some(info)) => info.platform_id.clone(),
+            (None, None) => Platform::from_path(rom_path)
+                .unwrap_or(Platform::Chip8)
+                .default_database_id()
+                .to_string(),
+        };
+        let profile = database
+            .platform(&platform_id)
+            .ok_or_else(|| format!("Unknown platform {}", platform_id))?;
+        let platform = Platform::from_database_id(&platform_id)
+            .filter(|platform| platform.is_supported())
+            .ok_or_else(|| {
+                format!(
+                    "Platform {} is not supported, only CHIP-8 programs run",
+                    platform_id
+                )
+            })?;
+
+        // Database quirks and tickrate only apply to the platform they were made for
+        let from_database = info.as_ref().filter(|info| info.platform_id == platform_id);
+        let mut quirks = from_database.map_or(profile.quirks, |info| info.quirks);
+        for (name, value) in &overrides.quirks {
+            quirks.set(name, *value)?;
+        }
+        let tickrate = overrides
+            .tickrate
+            .unwrap_or(from_database.map_or(profile.default_tickrate, |info| info.tickrate));
+
+        let palette = match (overrides.palette, &info) {
+            (Some(palette), _) => palette,
+            (None, Some(info)) if info.colors.len() >= 2 => Palette {
+                background: parse_color(&info.colors[0])?,
+                foreground: parse_color(&info.colors[1])?,
+            },
+            _ => Palette::default(),
+        };
+
+        Ok(RomSettings {
+            sha1,
+            title: info.as_ref().map(|info| info.title.clone()),
+            platform,
+            platform_id,
+            quirks,
+            tickrate,
+            palette,
+            key_hints: info.map(|info| info.keys).unwrap_or_default(),
+        })
+    }
+}
@@ -0,0 +1,54 @@
+// Emulation speed relative to the 60Hz frame rate
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Speed {
+    #[default]
+    Normal,
+    // n frames in the time of one
+    Fast(u32),
+    // As fast as the host allows
+    Uncapped,
+    // One frame in the time of n
+    Slow(u32),
+}
+
+impl Speed {
+    // 1x -> 2x -> 4x -> uncapped -> 1x, slow motion goes back to 1x
+    pub fn faster(self) -> Self {
+        match self {
+            Speed::Normal => Speed::Fast(2),
+            Speed::Fast(2) => Speed::Fast(4),
+            Speed::Fast(_) => Speed::Uncapped,
+            Speed::Uncapped | Speed::Slow(_) => Speed::Normal,
+        }
+    }
+
+    // 1x -> 1/2x -> 1/4x -> 1x, fast forward goes back to 1x
+    pub fn slower(self) -> Self {
+        match self {
+            Speed::Normal => Speed::Slow(2),
+            Speed::Slow(2) => Speed::Slow(4),
+            Speed::Slow(_) | Speed::Fast(_) | Speed::Uncapped => Speed::Normal,
+        }
+    }
+
+    pub fn frame_duration(self, normal: Duration) -> Duration {
+        match self {
+            Speed::Normal => normal,
+            Speed::Fast(factor) => normal / factor,
+            Speed::Uncapped => Duration::ZERO,
+            Speed::Slow(factor) => normal * factor,
+        }
+    }
+
+    pub fn label(self) -> Option<String> {
+        match self {
+            Speed::Normal => None,
+            Speed::Fast(factor) => Some(format!("{}X", factor)),
+            Speed::Uncapped => Some("MAX".to_string()),
+            Speed::Slow(factor) => Some(format!("1/{}X", factor)),
+        }
+    }
+}
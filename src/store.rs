@@ -0,0 +1,99 @@
+// Per-ROM settings kept between runs, in <config dir>/chip8/rom-settings.toml
+//
+// ["607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee"]
+// file = "pong.ch8"
+// tickrate = 20
+// palette = "#000000,#33FF33"
+// quirks = { vblank = false }
+// keys = { Up = 0x1, Down = 0x4 }
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Overrides;
+
+const STORE_FILE: &str = "rom-settings.toml";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StoredSettings {
+    // Last file name seen for this hash, to keep the store readable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(flatten)]
+    pub overrides: Overrides,
+}
+
+pub struct SettingsStore {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, StoredSettings>,
+}
+
+impl SettingsStore {
+    pub fn default_path() -> Result<PathBuf, String> {
+        dirs::config_dir()
+            .map(|directory| directory.join("chip8").join(STORE_FILE))
+            .ok_or_else(|| "Cannot find the user config directory".to_string())
+    }
+
+    // A missing file is an empty store. Without a path the store is empty and
+    // saving fails.
+    pub fn open(path: Option<&Path>) -> Result<Self, String> {
+        let entries = match path.map(|path| (path, fs::read_to_string(path))) {
+            Some((path, Ok(content))) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid settings file {}: {}", path.display(), e))?,
+            Some((path, Err(_))) if !path.exists() => BTreeMap::new(),
+            Some((path, Err(e))) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+            None => BTreeMap::new(),
+        };
+        Ok(SettingsStore {
+            path: path.map(Path::to_path_buf),
+            entries,
+        })
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<&StoredSettings> {
+        self.entries.get(sha1)
+    }
+
+    pub fn overrides(&self, sha1: &str) -> Overrides {
+        self.get(sha1)
+            .map(|stored| stored.overrides.clone())
+            .unwrap_or_default()
+    }
+
+    // New values are layered over the stored ones
+    pub fn update(&mut self, sha1: &str, file: &str, overrides: &Overrides) -> Result<(), String> {
+        let entry = self.entries.entry(sha1.to_string()).or_default();
+        entry.file = Some(file.to_string());
+        entry.overrides = overrides.layered_over(&entry.overrides);
+        self.save()
+    }
+
+    // Returns false if nothing was stored for this ROM
+    pub fn reset(&mut self, sha1: &str) -> Result<bool, String> {
+        if self.entries.remove(sha1).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("There is no config directory to save settings in")?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)
+                .map_err(|e| format!("Cannot create {}: {}", directory.display(), e))?;
+        }
+        let content = toml::to_string(&self.entries)
+            .map_err(|e| format!("Cannot serialize settings: {}", e))?;
+        fs::write(path, content).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+}
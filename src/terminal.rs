@@ -0,0 +1,315 @@
+// Renderer drawing in the terminal with ANSI escapes, e.g. over SSH.
+//
+// Half blocks show two display rows per character in the palette colors,
+// braille shows 2x4 pixels per character. Keys are read in raw mode; terminals
+// that cannot report key releases only send repeated presses, so there a key
+// counts as held for KEY_HOLD after its last press. Messages show on the line
+// under the status while the display is up, and are printed once it is gone.
+
+use std::{
+    collections::HashMap,
+    io::{self, Stdout, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{
+        self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::input::{self, KeyPoll};
+use crate::keymap::Bindings;
+use crate::palette::Palette;
+use crate::renderer::{Command, Renderer};
+
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerminalMode {
+    HalfBlocks,
+    Braille,
+}
+
+impl TerminalMode {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "half" => Ok(TerminalMode::HalfBlocks),
+            "braille" => Ok(TerminalMode::Braille),
+            _ => Err(format!(
+                "Unknown terminal mode {}, use half or braille",
+                name
+            )),
+        }
+    }
+}
+
+// SDL key names from the keymap, names without a terminal equivalent are skipped
+fn key_code(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c.to_ascii_lowercase()));
+    }
+    match name {
+        "Space" => Some(KeyCode::Char(' ')),
+        "Return" => Some(KeyCode::Enter),
+        "Backspace" => Some(KeyCode::Backspace),
+        "Tab" => Some(KeyCode::Tab),
+        "Up" => Some(KeyCode::Up),
+        "Down" => Some(KeyCode::Down),
+        "Left" => Some(KeyCode::Left),
+        "Right" => Some(KeyCode::Right),
+        _ => None,
+    }
+}
+
+fn color([r, g, b]: [u8; 3]) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+pub struct TerminalRenderer {
+    mode: TerminalMode,
+    out: Stdout,
+    palette: Palette,
+    key_bindings: HashMap<KeyCode, usize>,
+    // Clock of the key events
+    started: Instant,
+    // Keypad keys currently down, with the time of their last press
+    pressed_at: [Option<Instant>; 16],
+    // The terminal reports key releases
+    reports_releases: bool,
+    // The screen still shows what was printed before the first frame
+    needs_clear: bool,
+    status: Option<String>,
+    messages: Vec<String>,
+}
+
+impl TerminalRenderer {
+    pub fn new(mode: TerminalMode) -> Result<Self, String> {
+        let failed = |e: io::Error| format!("Cannot set up the terminal: {}", e);
+        terminal::enable_raw_mode().map_err(failed)?;
+        let mut out = io::stdout();
+        queue!(out, EnterAlternateScreen, Hide).map_err(failed)?;
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            queue!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )
+            .map_err(failed)?;
+        }
+        out.flush().map_err(failed)?;
+        Ok(TerminalRenderer {
+            mode,
+            out,
+            palette: Palette::default(),
+            key_bindings: HashMap::new(),
+            started: Instant::now(),
+            pressed_at: [None; 16],
+            reports_releases,
+            needs_clear: true,
+            status: None,
+            messages: vec![],
+        })
+    }
+
+    fn is_down(&self, key: usize, now: Instant) -> bool {
+        match self.pressed_at[key] {
+            Some(_) if self.reports_releases => true,
+            Some(pressed) => now.duration_since(pressed) < KEY_HOLD,
+            None => false,
+        }
+    }
+
+    fn draw_half_blocks(&mut self, pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> io::Result<()> {
+        for (row, lines) in pixels.chunks(2).enumerate() {
+            queue!(self.out, MoveTo(0, row as u16))?;
+            for x in 0..CHIP8_WIDTH {
+                let top = self.palette.color(lines[0][x]);
+                let bottom = self.palette.color(lines[1][x]);
+                queue!(
+                    self.out,
+                    SetForegroundColor(color(top)),
+                    SetBackgroundColor(color(bottom)),
+                    Print('▀')
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_braille(&mut self, pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> io::Result<()> {
+        // Dot bits of a braille character, by row then column
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        queue!(
+            self.out,
+            SetForegroundColor(color(self.palette.color(1))),
+            SetBackgroundColor(color(self.palette.color(0)))
+        )?;
+        for (row, lines) in pixels.chunks(4).enumerate() {
+            let line: String = (0..CHIP8_WIDTH / 2)
+                .map(|column| {
+                    let mut bits = 0;
+                    for (dy, dots) in DOTS.iter().enumerate() {
+                        for (dx, dot) in dots.iter().enumerate() {
+                            if lines[dy][column * 2 + dx] != 0 {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    char::from_u32(0x2800 + bits).unwrap_or(' ')
+                })
+                .collect();
+            queue!(self.out, MoveTo(0, row as u16), Print(line))?;
+        }
+        Ok(())
+    }
+
+    // On the line below the display, the last line of the last message under it
+    fn draw_status(&mut self) -> io::Result<()> {
+        let row = match self.mode {
+            TerminalMode::HalfBlocks => CHIP8_HEIGHT / 2,
+            TerminalMode::Braille => CHIP8_HEIGHT / 4,
+        };
+        let message = self
+            .messages
+            .last()
+            .and_then(|message| message.lines().last())
+            .unwrap_or_default();
+        queue!(
+            self.out,
+            ResetColor,
+            MoveTo(0, row as u16),
+            Clear(ClearType::CurrentLine),
+            Print(self.status.as_deref().unwrap_or_default()),
+            MoveTo(0, row as u16 + 1),
+            Clear(ClearType::CurrentLine),
+            Print(message)
+        )
+    }
+}
+
+impl Drop for TerminalRenderer {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = queue!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+        for message in &self.messages {
+            println!("{}", message);
+        }
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn clear_screen(&mut self) {
+        let _ = queue!(self.out, ResetColor, Clear(ClearType::All));
+    }
+
+    fn handle_event(&mut self) -> (KeyPoll, Vec<Command>) {
+        let mut commands = vec![];
+        let mut events = vec![];
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if let Some(&index) = self.key_bindings.get(&key.code) {
+                let read_at = Instant::now();
+                self.pressed_at[index] = match key.kind {
+                    KeyEventKind::Press | KeyEventKind::Repeat => Some(read_at),
+                    KeyEventKind::Release => None,
+                };
+                // Repeats of a held key are no new presses
+                let kind = match key.kind {
+                    KeyEventKind::Press => Some(input::KeyEventKind::Pressed),
+                    KeyEventKind::Repeat => None,
+                    KeyEventKind::Release => Some(input::KeyEventKind::Released),
+                };
+                events.extend(kind.map(|kind| input::KeyEvent {
+                    key: index,
+                    kind,
+                    timestamp: read_at.duration_since(self.started),
+                }));
+            }
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let command = match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Some(Command::Quit)
+                }
+                KeyCode::Esc => Some(Command::Quit),
+                KeyCode::F(5) => Some(Command::Pause),
+                KeyCode::F(6) => Some(Command::FrameAdvance),
+                KeyCode::F(7) => Some(Command::Step),
+                KeyCode::F(8) => Some(Command::Faster),
+                KeyCode::F(9) => Some(Command::Slower),
+                KeyCode::F(10) => Some(Command::Record),
+                KeyCode::F(12) => Some(Command::Screenshot),
+                KeyCode::Up => Some(Command::Up),
+                KeyCode::Down => Some(Command::Down),
+                KeyCode::Enter => Some(Command::Select),
+                _ => None,
+            };
+            commands.extend(command);
+        }
+        let now = Instant::now();
+        let mut keys = [false; 16];
+        for (key, down) in keys.iter_mut().enumerate() {
+            *down = self.is_down(key, now);
+        }
+        let poll = KeyPoll {
+            events,
+            keys,
+            timestamp: now.duration_since(self.started),
+        };
+        (poll, commands)
+    }
+
+    fn draw(&mut self, pixels: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        if self.needs_clear {
+            self.clear_screen();
+            self.needs_clear = false;
+        }
+        let _ = match self.mode {
+            TerminalMode::HalfBlocks => self.draw_half_blocks(pixels),
+            TerminalMode::Braille => self.draw_braille(pixels),
+        };
+        let _ = self.draw_status();
+        let _ = self.out.flush();
+    }
+
+    fn set_bindings(&mut self, bindings: &Bindings) -> Result<(), String> {
+        self.key_bindings = bindings
+            .keys
+            .iter()
+            .filter_map(|(name, &index)| key_code(name).map(|code| (code, index as usize)))
+            .collect();
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+    }
+
+    fn message(&mut self, message: &str) {
+        self.messages.push(message.to_string());
+        let _ = self.draw_status();
+        let _ = self.out.flush();
+    }
+
+    // The overlay is only drawn in the SDL window
+    fn set_overlay(&mut self, _lines: Option<Vec<String>>) {}
+}
@@ -0,0 +1,290 @@
+// Execution tracing, one record per executed instruction.
+//
+// Records go to stdout or a file, as text lines or JSON lines, or stay in a
+// ring buffer holding the most recent ones. The VM only keeps a tracer when
+// tracing is on, so untraced runs pay a single check per instruction.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::instruction::{disassemble, Instruction, InstructionClass};
+use crate::vm::VM;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterChange {
+    pub register: u8,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    // Instructions executed before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub registers: Vec<RegisterChange>,
+    // I after the instruction
+    pub i: u16,
+    pub writes: Vec<MemoryWrite>,
+}
+
+// e.g. 000042 0x21A 7001 ADD V0, 0x01  V0=05>06 I=0x2F0
+impl Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:06} {:#05X} {:04X} {:<18}",
+            self.cycle, self.pc, self.opcode, self.mnemonic
+        )?;
+        for change in &self.registers {
+            write!(
+                f,
+                " V{:X}={:02X}>{:02X}",
+                change.register, change.old, change.new
+            )?;
+        }
+        write!(f, " I={:#05X}", self.i)?;
+        for write in &self.writes {
+            write!(f, " [{:#05X}]={:02X}", write.address, write.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceTarget {
+    Stdout,
+    File(PathBuf),
+    // Keeps the last n records
+    Ring(usize),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("Unknown trace format {}, use text or json", name)),
+        }
+    }
+}
+
+// Instructions are traced when their address is in the range and their class
+// is listed, no range or no classes let everything through
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub classes: Vec<InstructionClass>,
+}
+
+impl TraceFilter {
+    // <start>-<end> in hex, e.g. 200-2FF
+    pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+        let invalid = || format!("Invalid address range {}, expected e.g. 200-2FF", text);
+        let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+        let parse = |value: &str| {
+            let value = value.trim();
+            let digits = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+                .unwrap_or(value);
+            u16::from_str_radix(digits, 16).map_err(|_| invalid())
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(start..=end)
+    }
+
+    fn accepts(&self, pc: u16, opcode: u16) -> bool {
+        if let Some(addresses) = &self.addresses {
+            if !addresses.contains(&pc) {
+                return false;
+            }
+        }
+        self.classes.is_empty()
+            || Instruction::from_u16(opcode)
+                .is_some_and(|instruction| self.classes.contains(&instruction.class()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub target: TraceTarget,
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+    Ring {
+        records: VecDeque<TraceRecord>,
+        capacity: usize,
+    },
+}
+
+// VM state before an instruction
+pub(crate) struct Snapshot {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub i: u16,
+}
+
+pub struct Tracer {
+    config: TraceConfig,
+    sink: Sink,
+    cycle: u64,
+    // First write error, reported by finish so the VM never has to handle it
+    error: Option<String>,
+}
+
+impl Tracer {
+    pub fn create(config: TraceConfig) -> Result<Self, String> {
+        let sink = match &config.target {
+            TraceTarget::Stdout => Sink::Stdout(io::stdout()),
+            TraceTarget::File(path) => Sink::File(BufWriter::new(
+                File::create(path)
+                    .map_err(|e| format!("Cannot create {}: {}", path.display(), e))?,
+            )),
+            TraceTarget::Ring(capacity) => Sink::Ring {
+                records: VecDeque::with_capacity(*capacity),
+                capacity: *capacity,
+            },
+        };
+        Ok(Tracer {
+            config,
+            sink,
+            cycle: 0,
+            error: None,
+        })
+    }
+
+    // Called by the VM after each instruction with the state from before it,
+    // filtered instructions still count as cycles
+    pub(crate) fn record(&mut self, before: &Snapshot, vm: &VM) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        let Snapshot { pc, opcode, .. } = *before;
+        if !self.config.filter.accepts(pc, opcode) {
+            return;
+        }
+        let (registers_before, registers) = (&before.registers, vm.registers());
+        let registers = (0..16u8)
+            .filter(|&index| registers_before[index as usize] != registers[index as usize])
+            .map(|index| RegisterChange {
+                register: index,
+                old: registers_before[index as usize],
+                new: registers[index as usize],
+            })
+            .collect();
+        // Only FX33 and FX55 write memory, both at the I they started with
+        let written = match Instruction::from_u16(opcode) {
+            Some(Instruction::Bcd { .. }) => before.i..before.i + 3,
+            Some(Instruction::Store { x }) => before.i..before.i + x as u16 + 1,
+            _ => 0..0,
+        };
+        let writes = written
+            .filter_map(|address| {
+                let value = *vm.memory().get(address as usize)?;
+                Some(MemoryWrite { address, value })
+            })
+            .collect();
+        let record = TraceRecord {
+            cycle,
+            pc,
+            opcode,
+            mnemonic: disassemble(opcode),
+            registers,
+            i: vm.i(),
+            writes,
+        };
+        self.emit(record);
+    }
+
+    fn emit(&mut self, record: TraceRecord) {
+        let format = self.config.format;
+        let written = match &mut self.sink {
+            Sink::Stdout(out) => write_record(&mut out.lock(), &record, format),
+            Sink::File(file) => write_record(file, &record, format),
+            Sink::Ring { records, capacity } => {
+                if *capacity > 0 {
+                    if records.len() == *capacity {
+                        records.pop_front();
+                    }
+                    records.push_back(record);
+                }
+                Ok(())
+            }
+        };
+        if let Err(e) = written {
+            self.error.get_or_insert_with(|| e.to_string());
+        }
+    }
+
+    // Oldest first, empty unless the target is a ring buffer
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord> {
+        let records = match &self.sink {
+            Sink::Ring { records, .. } => Some(records.iter()),
+            _ => None,
+        };
+        records.into_iter().flatten()
+    }
+
+    // Flushes the output, a ring buffer is printed to stdout
+    pub fn finish(mut self) -> Result<(), String> {
+        let format = self.config.format;
+        let flushed = match &mut self.sink {
+            Sink::Stdout(out) => out.flush(),
+            Sink::File(file) => file.flush(),
+            Sink::Ring { records, .. } => {
+                let mut out = io::stdout().lock();
+                records
+                    .iter()
+                    .try_for_each(|record| write_record(&mut out, record, format))
+            }
+        };
+        if let Err(e) = flushed {
+            self.error.get_or_insert_with(|| e.to_string());
+        }
+        match self.error {
+            Some(e) => Err(format!("Cannot write the trace: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_record(out: &mut impl Write, record: &TraceRecord, format: TraceFormat) -> io::Result<()> {
+    match format {
+        TraceFormat::Text => writeln!(out, "{}", record),
+        TraceFormat::Json => {
+            serde_json::to_writer(&mut *out, record)?;
+            writeln!(out)
+        }
+    }
+}
@@ -1,11 +1,131 @@
-use std::panic;
-use std::{fmt::Display, fs, thread, time::Duration};
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH, FONTS};
-use crate::renderer::{Renderer, SDLWrapper};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::cheat::{self, Cheats};
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH, FONTS, MEMORY_SIZE, PROGRAM_START, STACK_SIZE};
+use crate::coverage::Coverage;
+use crate::database::{sha1_hex, RomDatabase};
+use crate::gdb::GdbServer;
+use crate::heatmap::Heatmap;
+use crate::input::Input;
+use crate::instruction::disassemble;
+use crate::keymap::KeyMap;
+use crate::movie::{Movie, MovieRecorder};
+use crate::overlay;
+use crate::profile::Profiler;
+use crate::quirks::Quirks;
+use crate::recording::Recorder;
+use crate::reference::ReferenceTrace;
+use crate::remote::RemoteServer;
+use crate::renderer::{Command, Renderer};
+use crate::rom::Rom;
+use crate::screenshot;
+use crate::script::Script;
+use crate::settings::{Overrides, RomSettings};
+use crate::speed::Speed;
+use crate::store::SettingsStore;
+use crate::trace::{Snapshot, TraceConfig, Tracer};
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+const DEFAULT_TICKRATE: u32 = 15;
+
+// FX0A state, the COSMAC VIP only resumes once the pressed key is released
+#[derive(Clone, Copy, PartialEq)]
+enum KeyWait {
+    Running,
+    WaitingPress { register: usize },
+    WaitingRelease { register: usize, key: usize },
+}
+
+// Frontend independent options of a run
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    // Frame after which a screenshot is written
+    pub screenshot_after: Option<u64>,
+    // Screenshot pixels per display pixel, 1 is native resolution
+    pub screenshot_scale: u32,
+    // Stop after this many frames
+    pub frames: Option<u64>,
+    // Recording started with the ROM, .gif or raw RGB24 frames
+    pub record: Option<PathBuf>,
+    // Recording pixels per display pixel
+    pub record_scale: u32,
+    // Per-instruction trace of the whole run
+    pub trace: Option<TraceConfig>,
+    // Print a profile when the ROM stops
+    pub profile: bool,
+    // Coverage written when the ROM stops, lcov for .info files
+    pub coverage: Option<PathBuf>,
+    // Memory and display heatmap PNG, also saved with every screenshot
+    pub heatmap: Option<PathBuf>,
+    // Rhai script with hooks, for interactive and headless runs
+    pub script: Option<PathBuf>,
+    // Cheat lists per ROM, frozen every frame, none without a config directory
+    pub cheats: Option<PathBuf>,
+    // Cheat search and freeze commands read from stdin
+    pub cheat_console: bool,
+    // Input movie replayed instead of the keypad, see movie.rs
+    pub movie: Option<PathBuf>,
+    // Input movie recorded from the start
+    pub movie_record: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            screenshot_after: None,
+            screenshot_scale: 1,
+            frames: None,
+            record: None,
+            record_scale: 1,
+            trace: None,
+            profile: false,
+            coverage: None,
+            heatmap: None,
+            script: None,
+            cheats: None,
+            cheat_console: false,
+            movie: None,
+            movie_record: None,
+        }
+    }
+}
+
+// Why run_rom returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    Quit,
+    Launcher,
+}
+
+// Machine state for save states, without the settings and observers
+#[derive(Clone)]
+pub struct State {
+    memory: [u8; MEMORY_SIZE],
+    display_bits: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    pc: u16,
+    i: u16,
+    stack: Vec<u16>,
+    delay_timer: u8,
+    sound_timer: u8,
+    registers: [u8; 16],
+    keys: [bool; 16],
+    key_wait: KeyWait,
+    waiting_vblank: bool,
+    frame_cycles: u32,
+    frame_full: bool,
+    rng: SmallRng,
+    fault: Option<String>,
+}
 
 pub struct VM {
-    memory: [u8; 0x1000], // 4096 memoruse std::ops::Add;y
+    memory: [u8; MEMORY_SIZE], // 4096 memoruse std::ops::Add;y
     display_bits: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
     h: usize,
     w: usize,
@@ -16,14 +136,31 @@ pub struct VM {
     sound_timer: u8,
     registers: [u8; 16],
     keys: [bool; 16],
-    execution_paused: bool,
-    key_register: usize,
+    key_wait: KeyWait,
     display_changed: bool,
+    quirks: Quirks,
+    // Instructions executed per frame
+    tickrate: u32,
+    // Set by DXYN when the vblank quirk is on, ends the current frame
+    waiting_vblank: bool,
+    // Cycles run in the current frame
+    frame_cycles: u32,
+    // Every cycle of the current frame ran an instruction, for the profiler
+    frame_full: bool,
+    // CXNN randomness, seeded by the frontend so the core needs no OS entropy
+    rng: SmallRng,
+    // Why the VM halted, e.g. an unknown opcode or a return with an empty stack
+    fault: Option<String>,
+    // Only present while tracing
+    tracer: Option<Box<Tracer>>,
+    profiler: Option<Box<Profiler>>,
+    coverage: Option<Box<Coverage>>,
+    heatmap: Option<Box<Heatmap>>,
 }
 
 impl VM {
     pub fn new() -> Self {
-        let mut memory = [0; 0x1000];
+        let mut memory = [0; MEMORY_SIZE];
         FONTS
             .into_iter()
             .enumerate()
@@ -33,40 +170,187 @@ impl VM {
             display_bits: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
             h: 32,
             w: 64,
-            pc: 0x200,
+            pc: PROGRAM_START as u16,
             i: 0,
             stack: vec![],
             delay_timer: 0,
             sound_timer: 0,
             registers: [0; 16],
             keys: [false; 16],
-            execution_paused: false,
-            key_register: 0,
+            key_wait: KeyWait::Running,
             display_changed: false,
+            quirks: Quirks::default(),
+            tickrate: DEFAULT_TICKRATE,
+            waiting_vblank: false,
+            frame_cycles: 0,
+            frame_full: true,
+            rng: SmallRng::seed_from_u64(0),
+            fault: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            heatmap: None,
         }
     }
 
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    pub fn display(&self) -> &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT] {
+        &self.display_bits
+    }
+
+    // True once after each frame that changed the display
+    pub fn take_display_changed(&mut self) -> bool {
+        std::mem::take(&mut self.display_changed)
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    // (delay, sound)
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    // Set once an instruction could not run, the VM no longer executes any
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    // FX0A is blocking until a key is pressed and released
+    pub fn waiting_for_key(&self) -> bool {
+        self.key_wait != KeyWait::Running
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn opcode_at(&self, address: u16) -> Option<u16> {
+        let address = address as usize;
+        let bytes = self.memory.get(address..address + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer.map(Box::new);
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_deref()
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take().map(|tracer| *tracer)
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(Box::new);
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage.map(Box::new);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        self.heatmap = heatmap.map(Box::new);
+    }
+
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.take().map(|heatmap| *heatmap)
+    }
+
+    pub fn save_state(&self) -> State {
+        State {
+            memory: self.memory,
+            display_bits: self.display_bits,
+            pc: self.pc,
+            i: self.i,
+            stack: self.stack.clone(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            registers: self.registers,
+            keys: self.keys,
+            key_wait: self.key_wait,
+            waiting_vblank: self.waiting_vblank,
+            frame_cycles: self.frame_cycles,
+            frame_full: self.frame_full,
+            rng: self.rng.clone(),
+            fault: self.fault.clone(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &State) {
+        self.memory = state.memory;
+        self.display_bits = state.display_bits;
+        self.pc = state.pc;
+        self.i = state.i;
+        self.stack = state.stack.clone();
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.registers = state.registers;
+        self.keys = state.keys;
+        self.key_wait = state.key_wait;
+        self.waiting_vblank = state.waiting_vblank;
+        self.frame_cycles = state.frame_cycles;
+        self.frame_full = state.frame_full;
+        self.rng = state.rng.clone();
+        self.fault = state.fault.clone();
+        self.display_changed = true;
+    }
+
+    pub fn configure(&mut self, quirks: Quirks, tickrate: u32) {
+        self.quirks = quirks;
+        self.tickrate = tickrate;
+    }
+
     pub fn set_byte(&mut self, index: usize, value: u8) {
-        /*println!(
-            "Setting byte at index {:#06X?}, with value {:#04X?}",
-            index, value
-        );*/
         self.memory[index] = value;
     }
 
-    fn set_i_register(&mut self, value: u16) {
-        // println!("Setting byte in i register, with value {:#06X?}", value);
+    pub fn set_i_register(&mut self, value: u16) {
         self.i = value;
     }
 
-    fn set_register(&mut self, index: usize, value: u8) {
-        // println!(
-        //     "Setting byte in register {:#06X?}, with value {:#04X?}",
-        //     index, value
-        // );
+    pub fn set_register(&mut self, index: usize, value: u8) {
         self.registers[index] = value;
     }
 
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn set_timers(&mut self, delay: u8, sound: u8) {
+        (self.delay_timer, self.sound_timer) = (delay, sound);
+    }
+
     fn register_checker(&mut self, predicate: bool) {
         if predicate {
             self.registers[0xF] = 1;
@@ -77,34 +361,27 @@ impl VM {
 
     // Returns true if an overflow occured
     fn add_register(&mut self, index: usize, value: u8) {
-        // println!(
-        //     "Adding value {:#06X?} to register, {:#04X?} which contains {:#06X?}",
-        //     value, index, self.registers[index]
-        // );
-        // //self.print_registers();
         // Allow overflow some chip8 programs seems to use willingly overflow on u8 registers
         self.registers[index] = u8::wrapping_add(self.registers[index], value);
     }
 
     fn sub_register(&mut self, index: usize, value: u8) {
-        // println!(
-        //     "Subtracting value {:#06X?} to register, {:#04X?} which contains {:#06X?}",
-        //     value, index, self.registers[index]
-        // );
-        // //self.print_registers();
         // Allow overflow some chip8 programs seems to use willingly overflow on u8 registers
         self.registers[index] = u8::wrapping_sub(self.registers[index], value);
     }
 
-    fn push_stack(&mut self, value: u16) {
+    fn push_stack(&mut self, value: u16) -> Result<(), String> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(format!("Call with a full stack of {}", STACK_SIZE));
+        }
         self.stack.push(value);
+        Ok(())
     }
 
-    fn pop_stack(&mut self) -> u16 {
-        // Might want to check if the pop fails for debugging purposes
+    fn pop_stack(&mut self) -> Result<u16, String> {
         self.stack
             .pop()
-            .expect("Can't return from subroutine stack is empty")
+            .ok_or_else(|| "Return with an empty stack".to_string())
     }
 
     fn increment_pc(&mut self) {
@@ -113,22 +390,64 @@ impl VM {
 
     fn skip_instruction_if(&mut self, predicate: bool) {
         if predicate {
-            //           println!("Skipping instruction");
             self.increment_pc()
         }
     }
 
     fn jump_pc(&mut self, adress: u16) {
-        // println!("Jumping to {:#06X?}", adress);
         self.pc = adress
     }
 
-    fn get_current_instruction(&self) -> u16 {
-        ((self.memory[(self.pc) as usize] as u16) << 8) | self.memory[(self.pc + 1) as usize] as u16
+    fn reset_flag_if_logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // 8XY6/8XYE shift VY into VX, unless the shift quirk is on
+    fn shift_operand(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift {
+            self.registers[x as usize]
+        } else {
+            self.registers[y as usize]
+        }
+    }
+
+    // FX55/FX65
+    fn advance_i_after_memory_access(&mut self, x: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x {
+            x as u16
+        } else {
+            x as u16 + 1
+        };
+        self.set_i_register(self.i.wrapping_add(increment));
+    }
+
+    // The bytes from I that FX33, FX55 and FX65 access
+    fn memory_at_i(&self, count: u16) -> Result<std::ops::Range<usize>, String> {
+        let start = self.i as usize;
+        let end = start + count as usize;
+        if end > MEMORY_SIZE {
+            return Err(format!("I {:#05X} + {} is out of memory", self.i, count));
+        }
+        Ok(start..end)
+    }
+
+    fn key_in(&self, x: u8) -> Result<bool, String> {
+        let key = self.registers[x as usize];
+        self.keys
+            .get(key as usize)
+            .copied()
+            .ok_or_else(|| format!("V{:X} holds {:#04X}, not a key", x, key))
     }
 
-    fn decode_instruction(&mut self, context: &mut SDLWrapper) {
-        let instruction = self.get_current_instruction();
+    fn decode_instruction(&mut self) -> Result<(), String> {
+        let Some(instruction) = self.opcode_at(self.pc) else {
+            return Err(format!("PC {:#05X} is out of memory", self.pc));
+        };
         let hex_digits = (
             ((instruction & 0xF000) >> 12) as u8,
             ((instruction & 0x0F00) >> 8) as u8,
@@ -148,12 +467,11 @@ impl VM {
             0x00E0 => {
                 // Probably not the best performance wise
                 self.display_bits = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
-                context.clear_screen();
                 self.display_changed = true;
             }
 
             0x00EE => {
-                let adress = self.pop_stack();
+                let adress = self.pop_stack()?;
                 self.jump_pc(adress);
             }
 
@@ -164,7 +482,7 @@ impl VM {
                 }
                 0x02 => {
                     let adress = nnn;
-                    self.push_stack(self.pc);
+                    self.push_stack(self.pc)?;
                     self.jump_pc(adress)
                 }
                 0x03 => {
@@ -200,65 +518,79 @@ impl VM {
                     self.set_i_register(value)
                 }
                 0x0B => {
-                    let new_adress = nnn + (self.registers[0] as u16);
+                    // With the jump quirk this is BXNN, the high nibble picks the register
+                    let offset_register = if self.quirks.jump { x as usize } else { 0 };
+                    let new_adress = nnn + (self.registers[offset_register] as u16);
                     self.pc = new_adress;
                 }
                 0x0D => {
+                    // The starting position always wraps, the sprite itself is
+                    // clipped at the edges unless the wrap quirk is on
+                    let x_coordinate = (self.registers[x as usize] as usize) % self.w;
                     let y_coordinate = (self.registers[y as usize] as usize) % self.h;
                     self.set_register(0xF, 0);
                     let nibble = n as u16;
                     for i in 0..nibble {
-                        let new_y_coords = y_coordinate + (i as usize);
+                        let mut new_y_coords = y_coordinate + (i as usize);
                         if new_y_coords >= self.h {
-                            break;
+                            if !self.quirks.wrap {
+                                break;
+                            }
+                            new_y_coords %= self.h;
                         }
+                        let sprite_row = self.memory[(self.i.wrapping_add(i) & 0x0FFF) as usize];
                         for bit in 0..8 {
-                            let x = (self.registers[x as usize] as usize + bit as usize) % self.w;
+                            let mut x = x_coordinate + bit as usize;
                             if x >= self.w {
-                                break;
+                                if !self.quirks.wrap {
+                                    break;
+                                }
+                                x %= self.w;
                             }
-                            let color = (self.memory[(self.i + i) as usize] >> (7 - bit)) & 1;
+                            let color = (sprite_row >> (7 - bit)) & 1;
                             self.registers[0x0f] |= color & self.display_bits[new_y_coords][x];
                             self.display_bits[new_y_coords][x] ^= color;
                         }
                     }
                     self.display_changed = true;
+                    self.waiting_vblank = self.quirks.vblank;
                 }
                 0x0C => {
-                    let random_number = rand::random::<u8>() & nn;
+                    let random_number = self.rng.gen::<u8>() & nn;
                     self.set_register(x as usize, random_number);
                 }
                 _ => match hex_digits {
                     (0x0E, _, 0x09, 0x0E) => {
-                        println!(
-                            "Decoding... adress: {:#06X?} byte: {:#06X?}",
-                            self.pc, instruction
-                        );
-                        //self.keys.iter().for_each(|x| println!("{}", x));
-                        self.skip_instruction_if(self.keys[self.registers[x as usize] as usize])
+                        let pressed = self.key_in(x)?;
+                        self.skip_instruction_if(pressed)
                     }
                     (0x0E, _, 0x0A, 0x01) => {
-                        println!(
-                            "Decoding... adress: {:#06X?} byte: {:#06X?}",
-                            self.pc, instruction
-                        );
-                        //self.keys.iter().for_each(|x| println!("{}", x));
-                        self.skip_instruction_if(!self.keys[self.registers[x as usize] as usize])
+                        let pressed = self.key_in(x)?;
+                        self.skip_instruction_if(!pressed)
                     }
                     (0x0F, _, 0x0, 0x07) => self.registers[x as usize] = self.delay_timer,
                     (0x08, _, _, 0x00) => self.set_register(x as usize, self.registers[y as usize]),
-                    (0x08, _, _, 0x01) => self.set_register(
-                        x as usize,
-                        self.registers[y as usize] | self.registers[x as usize],
-                    ),
-                    (0x08, _, _, 0x02) => self.set_register(
-                        x as usize,
-                        self.registers[y as usize] & self.registers[x as usize],
-                    ),
-                    (0x08, _, _, 0x03) => self.set_register(
-                        x as usize,
-                        self.registers[y as usize] ^ self.registers[x as usize],
-                    ),
+                    (0x08, _, _, 0x01) => {
+                        self.set_register(
+                            x as usize,
+                            self.registers[y as usize] | self.registers[x as usize],
+                        );
+                        self.reset_flag_if_logic_quirk();
+                    }
+                    (0x08, _, _, 0x02) => {
+                        self.set_register(
+                            x as usize,
+                            self.registers[y as usize] & self.registers[x as usize],
+                        );
+                        self.reset_flag_if_logic_quirk();
+                    }
+                    (0x08, _, _, 0x03) => {
+                        self.set_register(
+                            x as usize,
+                            self.registers[y as usize] ^ self.registers[x as usize],
+                        );
+                        self.reset_flag_if_logic_quirk();
+                    }
                     (0x08, _, _, 0x04) => {
                         let x_value = self.registers[x as usize];
                         let y_value = self.registers[y as usize];
@@ -272,8 +604,9 @@ impl VM {
                         self.register_checker(x_value > y_value);
                     }
                     (0x08, _, _, 0x06) => {
-                        let shifted_bit = self.registers[x as usize] & 0x01;
-                        self.set_register(x as usize, self.registers[x as usize] >> 1);
+                        let value = self.shift_operand(x, y);
+                        let shifted_bit = value & 0x01;
+                        self.set_register(x as usize, value >> 1);
                         self.register_checker(shifted_bit == 1);
                     }
                     (0x08, _, _, 0x07) => {
@@ -284,26 +617,20 @@ impl VM {
                     }
 
                     (0x08, _, _, 0x0E) => {
-                        let last_bit = self.registers[x as usize] & 0b10000000;
-                        self.set_register(x as usize, self.registers[x as usize] << 1);
+                        let value = self.shift_operand(x, y);
+                        let last_bit = value & 0b10000000;
+                        self.set_register(x as usize, value << 1);
                         self.register_checker(last_bit == 0b10000000);
                     }
                     (0x0F, _, 0x05, 0x05) => {
-                        let adress = self.i;
-                        for i in 0..=x {
-                            let register_value = self.registers[i as usize];
-                            self.memory[(adress + (i as u16)) as usize] = register_value;
-                        }
+                        let range = self.memory_at_i(x as u16 + 1)?;
+                        self.memory[range].copy_from_slice(&self.registers[..=x as usize]);
+                        self.advance_i_after_memory_access(x);
                     }
                     (0x0F, _, 0x06, 0x05) => {
-                        // Read memory ?
-                        let adress = self.i;
-                        for i in 0..=x {
-                            self.set_register(
-                                i as usize,
-                                self.memory[(adress + (i as u16)) as usize],
-                            );
-                        }
+                        let range = self.memory_at_i(x as u16 + 1)?;
+                        self.registers[..=x as usize].copy_from_slice(&self.memory[range]);
+                        self.advance_i_after_memory_access(x);
                     }
                     (0x0F, _, 0x03, 0x03) => {
                         let mut register_value = self.registers[x as usize];
@@ -312,13 +639,11 @@ impl VM {
                         let tenths = register_value % 10;
                         register_value /= 10;
                         let hundreds = register_value % 10;
-                        let adress = self.i as usize;
-                        self.memory[adress] = hundreds;
-                        self.memory[adress + 1] = tenths;
-                        self.memory[adress + 2] = digit;
+                        let range = self.memory_at_i(3)?;
+                        self.memory[range].copy_from_slice(&[hundreds, tenths, digit]);
                     }
                     (0x0F, _, 0x01, 0x0E) => {
-                        self.i += (self.registers[x as usize]) as u16;
+                        self.i = self.i.wrapping_add(self.registers[x as usize] as u16);
                     }
                     (0x0F, _, 0x01, 0x05) => {
                         self.delay_timer = self.registers[x as usize];
@@ -327,77 +652,740 @@ impl VM {
                         self.sound_timer = self.registers[x as usize];
                     }
                     (0x0F, _, 0x00, 0x0A) => {
-                        self.execution_paused = true;
-                        self.key_register = x as usize;
+                        self.key_wait = KeyWait::WaitingPress {
+                            register: x as usize,
+                        };
                     }
                     (0x0F, _, 0x02, 0x09) => {
                         let digit = self.registers[x as usize] as u16;
                         self.i = digit * 5;
                     }
                     _ => {
-                        panic!("Uninplemented instruction {:#06X?}", instruction);
+                        return Err(format!("Unknown instruction {:04X}", instruction));
                     }
                 },
             },
         }
+        Ok(())
     }
 
-    fn read_rom(rom_path: &String) -> Self {
-        let mut result = Self::new();
-        println!("Trying to load rom: {}", rom_path);
-        let bytes_rom: Vec<u8> = fs::read(rom_path).expect("Cannot get bytes");
-        let start_program_adress = 0x200;
+    pub fn load_rom(&mut self, bytes_rom: &[u8]) -> Result<(), String> {
+        let available = self.memory.len() - PROGRAM_START;
+        if bytes_rom.len() > available {
+            return Err(format!(
+                "ROM is {} bytes, at most {} bytes fit in memory",
+                bytes_rom.len(),
+                available
+            ));
+        }
         bytes_rom
-            .into_iter()
+            .iter()
             .enumerate()
-            .for_each(|(index, value)| result.set_byte(index + start_program_adress, value));
-        result
-    }
-
-    fn cpu_cycle(&mut self, keys: [bool; 16], renderer_context: &mut SDLWrapper) {
-        self.keys = keys;
-        if self.execution_paused {
-            for i in 0..self.keys.len() {
-                if self.keys[i] {
-                    self.execution_paused = false;
-                    self.registers[self.key_register] = i as u8;
-                    break;
+            .for_each(|(index, &value)| self.set_byte(index + PROGRAM_START, value));
+        Ok(())
+    }
+
+    fn update_timers(&mut self) {
+        self.delay_timer = if self.delay_timer == 0 {
+            0
+        } else {
+            self.delay_timer - 1
+        };
+        self.sound_timer = if self.sound_timer == 0 {
+            0
+        } else {
+            self.sound_timer - 1
+        };
+    }
+
+    // A faulted VM stays halted, the PC is left on the faulting instruction
+    fn cpu_cycle(&mut self, input: &Input) {
+        if self.fault.is_some() {
+            return;
+        }
+        match self.key_wait {
+            KeyWait::Running if self.is_observed() => self.observed_instruction(),
+            KeyWait::Running => {
+                let pc = self.pc;
+                if let Err(fault) = self.decode_instruction() {
+                    self.halt(pc, fault);
                 }
             }
-        } else {
-            self.delay_timer = if self.delay_timer == 0 {
-                0
-            } else {
-                self.delay_timer - 1
-            };
-            self.sound_timer = if self.sound_timer == 0 {
-                0
-            } else {
-                println!("{}", self.sound_timer);
-                self.sound_timer - 1
-            };
-            self.decode_instruction(renderer_context);
-            if self.display_changed {
-                renderer_context.draw(&self.display_bits);
-                self.display_changed = false;
+            KeyWait::WaitingPress { register } => {
+                // Only a fresh press counts, keys held before FX0A are ignored
+                if let Some(key) = input.pressed().next() {
+                    self.key_wait = KeyWait::WaitingRelease { register, key };
+                }
+            }
+            KeyWait::WaitingRelease { register, key } => {
+                if input.released().any(|released| released == key) {
+                    self.set_register(register, key as u8);
+                    self.key_wait = KeyWait::Running;
+                }
             }
         }
     }
 
-    pub fn run_rom(rom_path: &String) -> Result<(), String> {
-        let mut virtual_machine = Self::read_rom(rom_path);
-        let mut renderer_context = SDLWrapper::initialize_sdl_renderer().unwrap();
-        'running: loop {
-            let event_message = renderer_context.handle_event();
-            if event_message.is_err() {
-                break 'running;
+    fn halt(&mut self, pc: u16, fault: String) {
+        self.pc = pc;
+        self.fault = Some(format!("{} at {:#05X}", fault, pc));
+    }
+
+    fn is_observed(&self) -> bool {
+        self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+            || self.heatmap.is_some()
+    }
+
+    // An instruction seen by the tracer, the profiler, the coverage or the heatmap
+    fn observed_instruction(&mut self) {
+        let before = Snapshot {
+            pc: self.pc,
+            opcode: self.opcode_at(self.pc).unwrap_or_default(),
+            registers: self.registers,
+            i: self.i,
+        };
+        // Draw frequency compares the display around DXYN
+        let display_before = (self.heatmap.is_some() && before.opcode & 0xF000 == 0xD000)
+            .then_some(self.display_bits);
+        if let Err(fault) = self.decode_instruction() {
+            self.halt(before.pc, fault);
+            return;
+        }
+        // Taken out for the duration of the call, the tracer reads the VM
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(&before, self);
+            self.tracer = Some(tracer);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(before.pc, before.opcode);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(before.pc, before.opcode, before.i);
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(before.pc, before.opcode, before.i);
+            if let Some(display_before) = &display_before {
+                heatmap.record_draw(display_before, &self.display_bits);
+            }
+        }
+    }
+
+    // A single instruction, timers are left alone
+    pub fn step(&mut self, input: &Input) {
+        self.keys = input.state();
+        self.cpu_cycle(input);
+    }
+
+    // One 60Hz frame: tickrate instructions then a timer tick.
+    // Timers keep running while FX0A blocks.
+    pub fn run_frame(&mut self, input: &Input) {
+        self.run_frame_while(input, |_| true);
+    }
+
+    // run_frame with a check before every cycle, a false stops the frame
+    // right there and the next call picks it up. Returns false if it was stopped.
+    pub(crate) fn run_frame_while(
+        &mut self,
+        input: &Input,
+        mut proceed: impl FnMut(&Self) -> bool,
+    ) -> bool {
+        loop {
+            if !proceed(self) {
+                return false;
+            }
+            if self.cycle(input) {
+                return true;
+            }
+        }
+    }
+
+    // One cycle of the current frame. The frame ends with a timer tick after
+    // tickrate cycles or a vblank wait, returns true if this cycle ended it.
+    pub fn cycle(&mut self, input: &Input) -> bool {
+        if self.frame_cycles == 0 {
+            self.keys = input.state();
+            self.waiting_vblank = false;
+            self.frame_full = true;
+        }
+        self.frame_full &= self.key_wait == KeyWait::Running;
+        self.cpu_cycle(input);
+        self.frame_cycles += 1;
+        if self.frame_cycles < self.tickrate && !self.waiting_vblank {
+            return false;
+        }
+        let full = self.frame_full && !self.waiting_vblank;
+        self.frame_cycles = 0;
+        self.update_timers();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame(full);
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.end_frame();
+        }
+        true
+    }
+
+    // Resolves the settings of a ROM and loads it into a new VM. Says nothing,
+    // see loaded_message.
+    pub(crate) fn boot(
+        rom: &Rom,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+    ) -> Result<(Self, RomSettings, Overrides), String> {
+        let sha1 = sha1_hex(&rom.bytes);
+        // Command line overrides win over the settings saved for this ROM
+        let overrides = overrides.layered_over(&store.overrides(&sha1).layered_over(&rom.settings));
+        let settings = RomSettings::resolve(database, rom.path(), sha1, &overrides)?;
+        rom.check_size()?;
+
+        let mut virtual_machine = Self::new();
+        virtual_machine.seed(time_seed());
+        virtual_machine.configure(settings.quirks, settings.tickrate);
+        virtual_machine.load_rom(&rom.bytes)?;
+        if let Some(trace) = &options.trace {
+            virtual_machine.set_tracer(Some(Tracer::create(trace.clone())?));
+        }
+        if options.profile {
+            virtual_machine.set_profiler(Some(Profiler::new()));
+        }
+        if let Some(path) = &options.coverage {
+            virtual_machine.set_coverage(Some(Coverage::new(path, &rom.name, &rom.bytes)));
+        }
+        if let Some(path) = &options.heatmap {
+            virtual_machine.set_heatmap(Some(Heatmap::new(path)));
+        }
+        Ok((virtual_machine, settings, overrides))
+    }
+
+    // What boot found out about the ROM
+    pub(crate) fn loaded_message(rom: &Rom, settings: &RomSettings) -> String {
+        match &settings.title {
+            Some(title) => format!("Loaded {}: {} ({})", rom.name, title, settings.platform_id),
+            None => format!(
+                "Loaded {}: unknown ROM {} ({})",
+                rom.name, settings.sha1, settings.platform_id
+            ),
+        }
+    }
+
+    // What a run records and freezes from its start
+    fn start_messages(
+        options: &RunOptions,
+        cheats: &Cheats,
+        movie_recorder: &Option<MovieRecorder>,
+    ) -> Vec<String> {
+        let mut messages = vec![];
+        if let Some(path) = &options.record {
+            messages.push(format!("Recording to {}", path.display()));
+        }
+        if let (Some(path), Some(_)) = (&options.movie_record, movie_recorder) {
+            messages.push(format!("Recording input to {}", path.display()));
+        }
+        if !cheats.frozen().is_empty() {
+            messages.push(format!("Freezing {} saved cheats", cheats.frozen().len()));
+        }
+        messages
+    }
+
+    // The movie to replay and the one to record, both reseed the VM so CXNN
+    // replays as well
+    fn load_movies(
+        options: &RunOptions,
+        settings: &RomSettings,
+        vm: &mut VM,
+    ) -> Result<(Option<Movie>, Option<MovieRecorder>), String> {
+        let movie = match &options.movie {
+            Some(path) => {
+                let movie = Movie::load(path)?;
+                if movie.sha1 != settings.sha1 {
+                    return Err(format!(
+                        "Movie {} was recorded with another ROM, {}",
+                        path.display(),
+                        movie.sha1
+                    ));
+                }
+                vm.seed(movie.seed);
+                Some(movie)
+            }
+            None => None,
+        };
+        let recorder = options.movie_record.as_ref().map(|path| {
+            // A replay recorded again keeps its seed
+            let seed = movie.as_ref().map_or_else(time_seed, |movie| movie.seed);
+            vm.seed(seed);
+            MovieRecorder::new(path, &settings.sha1, seed)
+        });
+        Ok((movie, recorder))
+    }
+
+    fn load_script(options: &RunOptions, vm: &mut VM) -> Result<Option<Script>, String> {
+        match &options.script {
+            Some(path) => Script::load(path, vm).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Ends the trace, saves the coverage and the heatmap when the ROM stops.
+    // Returns the profile report and what was saved, for the user.
+    pub(crate) fn finish_observers(&mut self) -> Result<Vec<String>, String> {
+        let mut messages = vec![];
+        if let Some(profiler) = self.take_profiler() {
+            messages.push(profiler.report());
+        }
+        if let Some(coverage) = self.take_coverage() {
+            messages.push(coverage.finish()?);
+        }
+        if let Some(heatmap) = self.take_heatmap() {
+            messages.push(heatmap.finish(&self.memory)?);
+        }
+        if let Some(tracer) = self.take_tracer() {
+            tracer.finish()?;
+        }
+        Ok(messages)
+    }
+
+    fn save_screenshot(
+        &self,
+        rom: &Rom,
+        frame: u64,
+        settings: &RomSettings,
+        options: &RunOptions,
+    ) -> Result<Vec<String>, String> {
+        let path = screenshot::file_name(&rom.name, frame, "png");
+        screenshot::save_png(
+            &path,
+            &self.display_bits,
+            &settings.palette,
+            options.screenshot_scale,
+        )?;
+        let mut messages = vec![format!("Saved screenshot {}", path.display())];
+        if let Some(heatmap) = &self.heatmap {
+            let path = screenshot::file_name(&rom.name, frame, "heatmap.png");
+            heatmap.save(&path, &self.memory)?;
+            messages.push(format!("Saved heatmap {}", path.display()));
+        }
+        Ok(messages)
+    }
+
+    pub fn run_rom(
+        rom: &Rom,
+        keymap: &KeyMap,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+        renderer_context: &mut impl Renderer,
+    ) -> Result<RunOutcome, String> {
+        let (mut virtual_machine, settings, overrides) =
+            Self::boot(rom, database, overrides, store, options)?;
+        let mut script = Self::load_script(options, &mut virtual_machine)?;
+        let mut cheats = Cheats::load(options.cheats.as_deref(), &settings.sha1, &rom.name)?;
+        let (movie, mut movie_recorder) =
+            Self::load_movies(options, &settings, &mut virtual_machine)?;
+        // Printing would garble the terminal frontend, it shows messages itself
+        renderer_context.message(&Self::loaded_message(rom, &settings));
+        for message in Self::start_messages(options, &cheats, &movie_recorder) {
+            renderer_context.message(&message);
+        }
+        if options.cheat_console {
+            renderer_context.message("Cheat console ready, type help");
+        }
+        // Script text currently shown in the overlay
+        let mut script_text = vec![];
+        let mut bindings = keymap.bindings_for(&rom.name, &settings.key_hints);
+        overrides.apply_bindings(&mut bindings);
+        renderer_context.set_bindings(&bindings)?;
+        renderer_context.set_palette(settings.palette);
+        renderer_context.draw(&virtual_machine.display_bits);
+        let mut input = Input::new();
+        let mut frame = 0;
+        let mut recorder = match &options.record {
+            Some(path) => Some(Recorder::create(
+                path,
+                settings.palette,
+                options.record_scale,
+            )?),
+            None => None,
+        };
+        let mut paused = false;
+        let mut speed = Speed::default();
+        let mut show_overlay = false;
+        let outcome = 'frames: loop {
+            let frame_start = Instant::now();
+            let (mut poll, commands) = renderer_context.handle_event();
+            let was = (paused, speed, virtual_machine.fault.is_some());
+            // Frame advance and step pause the ROM first
+            let mut advance = false;
+            let mut step = false;
+            for command in commands {
+                match command {
+                    Command::Quit => break 'frames RunOutcome::Quit,
+                    Command::Launcher => break 'frames RunOutcome::Launcher,
+                    // A failed screenshot is reported, the ROM keeps running
+                    Command::Screenshot => {
+                        match virtual_machine.save_screenshot(rom, frame, &settings, options) {
+                            Ok(messages) => messages
+                                .iter()
+                                .for_each(|message| renderer_context.message(message)),
+                            Err(e) => {
+                                renderer_context.message(&format!("Screenshot failed: {}", e))
+                            }
+                        }
+                    }
+                    // F10 toggles recording to a new GIF, like screenshots a
+                    // failure is reported and the ROM keeps running
+                    Command::Record => match recorder.take() {
+                        Some(active) => match active.finish() {
+                            Ok(message) => renderer_context.message(&message),
+                            Err(e) => renderer_context.message(&format!("Recording failed: {}", e)),
+                        },
+                        None => {
+                            let path = screenshot::file_name(&rom.name, frame, "gif");
+                            match Recorder::create(&path, settings.palette, options.record_scale) {
+                                Ok(started) => {
+                                    recorder = Some(started);
+                                    renderer_context
+                                        .message(&format!("Recording to {}", path.display()));
+                                }
+                                Err(e) => {
+                                    renderer_context.message(&format!("Recording failed: {}", e))
+                                }
+                            }
+                        }
+                    },
+                    Command::Pause => paused = !paused,
+                    Command::FrameAdvance => (paused, advance) = (true, true),
+                    Command::Step => (paused, step) = (true, true),
+                    Command::Faster => speed = speed.faster(),
+                    Command::Slower => speed = speed.slower(),
+                    Command::Overlay => {
+                        show_overlay = !show_overlay;
+                        if !show_overlay {
+                            renderer_context.set_overlay(None);
+                            script_text.clear();
+                            virtual_machine.display_changed = true;
+                        }
+                    }
+                    Command::Up | Command::Down | Command::Select => {}
+                }
+            }
+            while let Some(line) = options.cheat_console.then(cheat::console_line).flatten() {
+                match cheats.command(&line, &mut virtual_machine) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => renderer_context.message(&output),
+                    Err(e) => renderer_context.message(&e),
+                }
+            }
+            // The player takes over once the movie ends, movies only hold states
+            if let Some(keys) = movie.as_ref().and_then(|movie| movie.keys(frame + 1)) {
+                poll.events.clear();
+                poll.keys = keys;
+            }
+            if let Some(script) = &script {
+                poll.keys = held_keys(poll.keys, script);
+            }
+            input.apply(poll);
+            cheats.apply(&mut virtual_machine);
+            if step {
+                virtual_machine.step(&input);
+            }
+            if !paused || advance {
+                match &mut script {
+                    Some(script) => script.run_frame(&mut virtual_machine, &input, frame + 1)?,
+                    None => virtual_machine.run_frame(&input),
+                }
+                frame += 1;
+                if let Some(movie_recorder) = &mut movie_recorder {
+                    movie_recorder.record(frame, input.state());
+                }
+                // A recording that cannot be written is dropped
+                if let Some(active) = &mut recorder {
+                    if let Err(e) = active.add_frame(&virtual_machine.display_bits) {
+                        renderer_context.message(&format!("Recording failed: {}", e));
+                        recorder = None;
+                    }
+                }
+                if options.screenshot_after == Some(frame) {
+                    for message in
+                        virtual_machine.save_screenshot(rom, frame, &settings, options)?
+                    {
+                        renderer_context.message(&message);
+                    }
+                }
+                if options.frames == Some(frame)
+                    || script.as_ref().is_some_and(Script::quit_requested)
+                {
+                    break RunOutcome::Quit;
+                }
+            }
+            let faulted = virtual_machine.fault.is_some();
+            // The overlay shows the fault and the state the VM halted in
+            if faulted && !was.2 {
+                show_overlay = true;
+            }
+            if (paused, speed, faulted) != was {
+                let status = match (faulted, paused, speed.label()) {
+                    (true, _, _) => Some("FAULT".to_string()),
+                    (false, true, _) => Some("PAUSED".to_string()),
+                    (false, false, label) => label,
+                };
+                renderer_context.set_status(status);
+                virtual_machine.display_changed = true;
+            }
+            if let Some(script) = &script {
+                for line in script.take_output() {
+                    renderer_context.message(&line);
+                }
+            }
+            // The overlay follows the VM every frame, even while paused
+            if show_overlay {
+                renderer_context.set_overlay(Some(overlay::lines(&virtual_machine)));
+                virtual_machine.display_changed = true;
+            } else if let Some(script) = &script {
+                let text = script.text();
+                if text != script_text {
+                    renderer_context.set_overlay((!text.is_empty()).then(|| text.clone()));
+                    script_text = text;
+                    virtual_machine.display_changed = true;
+                }
+            }
+            if virtual_machine.display_changed {
+                renderer_context.draw(&virtual_machine.display_bits);
+                virtual_machine.display_changed = false;
+            }
+            let frame_duration = speed.frame_duration(FRAME_DURATION);
+            if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        };
+        let mut messages = vec![];
+        if let Some(recorder) = recorder {
+            messages.push(
+                recorder
+                    .finish()
+                    .unwrap_or_else(|e| format!("Recording failed: {}", e)),
+            );
+        }
+        if let Some(movie_recorder) = movie_recorder {
+            messages.push(movie_recorder.finish()?);
+        }
+        messages.extend(virtual_machine.finish_observers()?);
+        for message in messages {
+            renderer_context.message(&message);
+        }
+        // The next ROM or the launcher starts without them
+        renderer_context.set_status(None);
+        renderer_context.set_overlay(None);
+        Ok(outcome)
+    }
+
+    // Runs without a window and as fast as possible, with the keys of the
+    // movie if there is one, until the frame limit or, without one, until the
+    // screenshot is taken or the movie ends
+    pub fn run_headless(
+        rom: &Rom,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+    ) -> Result<(), String> {
+        if options.frames.is_none() && options.screenshot_after.is_none() && options.movie.is_none()
+        {
+            return Err("Headless runs need --frames, --screenshot-after or --movie".to_string());
+        }
+        let (mut virtual_machine, settings, _) =
+            Self::boot(rom, database, overrides, store, options)?;
+        println!("{}", Self::loaded_message(rom, &settings));
+        let (movie, mut movie_recorder) =
+            Self::load_movies(options, &settings, &mut virtual_machine)?;
+        let last_frame = options
+            .frames
+            .or(options.screenshot_after)
+            .or(movie.as_ref().map(|movie| movie.frames))
+            .unwrap_or_default();
+        let mut recorder = match &options.record {
+            Some(path) => Some(Recorder::create(
+                path,
+                settings.palette,
+                options.record_scale,
+            )?),
+            None => None,
+        };
+        let mut script = Self::load_script(options, &mut virtual_machine)?;
+        let cheats = Cheats::load(options.cheats.as_deref(), &settings.sha1, &rom.name)?;
+        for message in Self::start_messages(options, &cheats, &movie_recorder) {
+            println!("{}", message);
+        }
+        let mut input = Input::new();
+        let mut fault = None;
+        for frame in 1..=last_frame {
+            cheats.apply(&mut virtual_machine);
+            let keys = movie
+                .as_ref()
+                .and_then(|movie| movie.keys(frame))
+                .unwrap_or_default();
+            let keys = match &script {
+                Some(script) => held_keys(keys, script),
+                None => keys,
+            };
+            input.update(keys, FRAME_DURATION * frame as u32);
+            match &mut script {
+                Some(script) => script.run_frame(&mut virtual_machine, &input, frame)?,
+                None => virtual_machine.run_frame(&input),
+            }
+            if let Some(movie_recorder) = &mut movie_recorder {
+                movie_recorder.record(frame, input.state());
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.add_frame(&virtual_machine.display_bits)?;
+            }
+            if let Some(script) = &script {
+                script
+                    .take_output()
+                    .iter()
+                    .for_each(|line| println!("{}", line));
             }
-            let event = event_message.unwrap();
-            virtual_machine.cpu_cycle(event, &mut renderer_context);
-            thread::sleep(Duration::from_millis(2));
+            if options.screenshot_after == Some(frame) {
+                for message in virtual_machine.save_screenshot(rom, frame, &settings, options)? {
+                    println!("{}", message);
+                }
+            }
+            if let Some(halted) = &virtual_machine.fault {
+                fault = Some(format!("VM halted in frame {}: {}", frame, halted));
+                break;
+            }
+            if script.as_ref().is_some_and(Script::quit_requested) {
+                break;
+            }
+        }
+        // The recording and the observers show how the VM got to a fault
+        if let Some(recorder) = recorder {
+            println!("{}", recorder.finish()?);
+        }
+        if let Some(movie_recorder) = movie_recorder {
+            println!("{}", movie_recorder.finish()?);
+        }
+        for message in virtual_machine.finish_observers()? {
+            println!("{}", message);
+        }
+        match fault {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+
+    // Runs headless, driven by remote control clients, see remote.rs. Without
+    // a ROM the first client has to load one.
+    pub fn run_remote(
+        rom: Option<Rom>,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+        address: &str,
+    ) -> Result<(), String> {
+        let mut server = RemoteServer::new(database, overrides, store, options);
+        if let Some(rom) = rom {
+            server.load(rom)?;
+        }
+        server.serve(address)
+    }
+
+    // Runs headless under the control of a GDB client, see gdb.rs
+    pub fn run_gdb(
+        rom: &Rom,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+        address: &str,
+    ) -> Result<(), String> {
+        let (virtual_machine, settings, _) = Self::boot(rom, database, overrides, store, options)?;
+        println!("{}", Self::loaded_message(rom, &settings));
+        let mut server = GdbServer::new(virtual_machine);
+        server.serve(address)?;
+        for message in server.into_vm().finish_observers()? {
+            println!("{}", message);
         }
         Ok(())
     }
+
+    // Runs headless while comparing the state before every instruction with a
+    // reference trace, stops at the first difference
+    pub fn run_lockstep(
+        rom: &Rom,
+        database: &RomDatabase,
+        overrides: &Overrides,
+        store: &SettingsStore,
+        options: &RunOptions,
+        reference: &ReferenceTrace,
+    ) -> Result<(), String> {
+        let (mut virtual_machine, settings, _) =
+            Self::boot(rom, database, overrides, store, options)?;
+        println!("{}", Self::loaded_message(rom, &settings));
+        let input = Input::new();
+        let mut states = reference.states.iter().peekable();
+        let mut previous = None;
+        let mut failure = None;
+        while failure.is_none() && states.peek().is_some() {
+            virtual_machine.run_frame_while(&input, |vm| {
+                if let Some(fault) = &vm.fault {
+                    failure = Some(fault.clone());
+                    return false;
+                }
+                if vm.key_wait != KeyWait::Running {
+                    failure = Some(format!(
+                        "VM waits for a key at {:#05X}, lockstep runs have no input",
+                        vm.pc
+                    ));
+                    return false;
+                }
+                let Some(state) = states.next() else {
+                    return false;
+                };
+                if let Some(diff) = state.diff(vm) {
+                    let after = match previous {
+                        Some((pc, opcode)) => {
+                            format!("after {:#05X} {}", pc, disassemble(opcode))
+                        }
+                        None => "before the first instruction".to_string(),
+                    };
+                    // The table does not survive the Debug formatting of errors
+                    println!("{}", diff);
+                    failure = Some(format!(
+                        "Diverged from reference line {}, {}",
+                        state.line, after
+                    ));
+                    return false;
+                }
+                previous = Some((vm.pc, vm.opcode_at(vm.pc).unwrap_or_default()));
+                true
+            });
+        }
+        for message in virtual_machine.finish_observers()? {
+            println!("{}", message);
+        }
+        match failure {
+            Some(failure) => Err(failure),
+            None => {
+                println!(
+                    "Matched all {} reference instructions",
+                    reference.states.len()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display for VM {
@@ -405,3 +1393,17 @@ impl Display for VM {
         write!(f, "{:x?}", self.memory[0x200])
     }
 }
+
+// CXNN seed of runs that are not replays
+fn time_seed() -> u64 {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+    now.map(|time| time.as_nanos() as u64).unwrap_or_default()
+}
+
+// The player's keys plus those held by the script
+fn held_keys(mut keys: [bool; 16], script: &Script) -> [bool; 16] {
+    for (key, held) in keys.iter_mut().zip(script.keys()) {
+        *key |= held;
+    }
+    keys
+}
@@ -0,0 +1,212 @@
+// Browser frontend: draws to a canvas, takes keyboard events from the page and
+// beeps through WebAudio. The page forwards key events and calls frame() from
+// requestAnimationFrame, see web/index.html.
+
+use std::{collections::HashMap, time::Duration};
+
+use wasm_bindgen::{prelude::*, Clamped};
+use web_sys::{
+    AudioContext, CanvasRenderingContext2d, GainNode, HtmlCanvasElement, ImageData, OscillatorType,
+};
+
+use crate::constants::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::database::{sha1_hex, RomDatabase};
+use crate::input::{Input, KeyEvent, KeyEventKind, KeyPoll};
+use crate::keymap::KeyMap;
+use crate::palette::Palette;
+use crate::rom::Rom;
+use crate::settings::RomSettings;
+use crate::vm::VM;
+
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_VOLUME: f32 = 0.1;
+
+// KeyboardEvent.code to the SDL key names used by key maps, e.g. KeyQ -> Q
+fn key_name(code: &str) -> &str {
+    match code {
+        "ArrowUp" => "Up",
+        "ArrowDown" => "Down",
+        "ArrowLeft" => "Left",
+        "ArrowRight" => "Right",
+        "Enter" => "Return",
+        _ => code
+            .strip_prefix("Key")
+            .or_else(|| code.strip_prefix("Digit"))
+            .unwrap_or(code),
+    }
+}
+
+fn js_error(message: String) -> JsValue {
+    JsValue::from_str(&message)
+}
+
+struct Beeper {
+    context: AudioContext,
+    gain: GainNode,
+}
+
+impl Beeper {
+    // A square wave that is always running, muted while the sound timer is 0
+    fn new() -> Result<Self, JsValue> {
+        let context = AudioContext::new()?;
+        let oscillator = context.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Square);
+        oscillator.frequency().set_value(BEEP_FREQUENCY);
+        let gain = context.create_gain()?;
+        gain.gain().set_value(0.0);
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&context.destination())?;
+        oscillator.start()?;
+        Ok(Beeper { context, gain })
+    }
+
+    fn set_playing(&self, playing: bool) {
+        // Browsers keep audio suspended until the page got a user gesture
+        if playing {
+            let _ = self.context.resume();
+        }
+        let volume = if playing { BEEP_VOLUME } else { 0.0 };
+        self.gain.gain().set_value(volume);
+    }
+}
+
+#[wasm_bindgen(js_name = Emulator)]
+pub struct WebEmulator {
+    vm: VM,
+    input: Input,
+    keys: [bool; 16],
+    // Key events since the last frame, so a tap between two frames counts
+    events: Vec<KeyEvent>,
+    key_bindings: HashMap<String, usize>,
+    palette: Palette,
+    context: CanvasRenderingContext2d,
+    beeper: Option<Beeper>,
+    // Time of the next frame, in milliseconds since page load
+    next_frame: Option<f64>,
+}
+
+#[wasm_bindgen(js_class = Emulator)]
+impl WebEmulator {
+    // The canvas is drawn at the display resolution, scale it with CSS
+    // (image-rendering: pixelated)
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas: HtmlCanvasElement, rom: &[u8], seed: f64) -> Result<WebEmulator, JsValue> {
+        let rom = Rom::from_bytes("rom", rom.to_vec()).map_err(js_error)?;
+        let sha1 = sha1_hex(&rom.bytes);
+        let settings =
+            RomSettings::resolve(&RomDatabase::bundled(), rom.path(), sha1, &rom.settings)
+                .map_err(js_error)?;
+        rom.check_size().map_err(js_error)?;
+        let mut vm = VM::new();
+        vm.seed(seed as u64);
+        vm.configure(settings.quirks, settings.tickrate);
+        vm.load_rom(&rom.bytes).map_err(js_error)?;
+        let key_bindings = KeyMap::default()
+            .bindings_for(&rom.name, &settings.key_hints)
+            .keys
+            .into_iter()
+            .map(|(name, index)| (name.to_uppercase(), index as usize))
+            .collect();
+
+        canvas.set_width(CHIP8_WIDTH as u32);
+        canvas.set_height(CHIP8_HEIGHT as u32);
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("Canvas has no 2d context"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        let emulator = WebEmulator {
+            vm,
+            input: Input::new(),
+            keys: [false; 16],
+            events: vec![],
+            key_bindings,
+            palette: settings.palette,
+            context,
+            // Pages without audio still run, silently
+            beeper: Beeper::new().ok(),
+            next_frame: None,
+        };
+        emulator.draw()?;
+        Ok(emulator)
+    }
+
+    // Returns true if the key is on the keypad, so the page can prevent the default action
+    pub fn key_down(&mut self, code: &str) -> bool {
+        self.set_key(code, true)
+    }
+
+    pub fn key_up(&mut self, code: &str) -> bool {
+        self.set_key(code, false)
+    }
+
+    // Called from requestAnimationFrame with its timestamp, runs the 60Hz
+    // frames due since the last call
+    pub fn frame(&mut self, timestamp: f64) -> Result<(), JsValue> {
+        const FRAME_MILLISECONDS: f64 = 1000.0 / 60.0;
+        let mut next_frame = self.next_frame.unwrap_or(timestamp);
+        // Do not try to catch up after the tab was in the background
+        if timestamp - next_frame > FRAME_MILLISECONDS * 4.0 {
+            next_frame = timestamp;
+        }
+        while next_frame <= timestamp {
+            self.input.apply(KeyPoll {
+                events: std::mem::take(&mut self.events),
+                keys: self.keys,
+                timestamp: Duration::from_secs_f64(next_frame / 1000.0),
+            });
+            self.vm.run_frame(&self.input);
+            next_frame += FRAME_MILLISECONDS;
+        }
+        self.next_frame = Some(next_frame);
+        if let Some(beeper) = &self.beeper {
+            beeper.set_playing(self.vm.sound_playing());
+        }
+        if self.vm.take_display_changed() {
+            self.draw()?;
+        }
+        match self.vm.fault() {
+            Some(fault) => Err(js_error(format!("VM halted: {}", fault))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl WebEmulator {
+    fn set_key(&mut self, code: &str, down: bool) -> bool {
+        match self.key_bindings.get(&key_name(code).to_uppercase()) {
+            Some(&index) => {
+                self.keys[index] = down;
+                // Pages do not pass the event time, the next frame's is close
+                self.events.push(KeyEvent {
+                    key: index,
+                    kind: match down {
+                        true => KeyEventKind::Pressed,
+                        false => KeyEventKind::Released,
+                    },
+                    timestamp: Duration::from_secs_f64(self.next_frame.unwrap_or(0.0) / 1000.0),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn draw(&self) -> Result<(), JsValue> {
+        let data: Vec<u8> = self
+            .vm
+            .display()
+            .iter()
+            .flatten()
+            .flat_map(|&pixel| {
+                let [r, g, b] = self.palette.color(pixel);
+                [r, g, b, 0xFF]
+            })
+            .collect();
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&data),
+            CHIP8_WIDTH as u32,
+            CHIP8_HEIGHT as u32,
+        )?;
+        self.context.put_image_data(&image, 0.0, 0.0)
+    }
+}
@@ -0,0 +1,191 @@
+// Drives GdbServer over TCP with a scripted client
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+use chip8::{gdb::GdbServer, vm::VM};
+
+// 200: LD V0, 05
+// 202: LD I, 300
+// 204: ADD V0, 01
+// 206: JP 204
+const PROGRAM: [u8; 8] = [0x60, 0x05, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x04];
+
+struct Client {
+    stream: TcpStream,
+    // Console output the server sent in O packets
+    console: String,
+}
+
+impl Client {
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+        loop {
+            let reply = self.reply();
+            match reply.strip_prefix('O') {
+                Some(output) if reply != "OK" => self.console += &unhex(output),
+                _ => return reply,
+            }
+        }
+    }
+
+    fn reply(&mut self) -> String {
+        let mut reply = vec![];
+        let mut in_packet = false;
+        loop {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            match (in_packet, byte[0]) {
+                (false, b'$') => in_packet = true,
+                (false, _) => {}
+                (true, b'#') => break,
+                (true, byte) => reply.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+fn start(program: &[u8]) -> (Client, JoinHandle<Result<(), String>>) {
+    let mut vm = VM::new();
+    vm.load_rom(program).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || GdbServer::new(vm).serve_listener(listener));
+    let stream = TcpStream::connect(address).unwrap();
+    let console = String::new();
+    (Client { stream, console }, server)
+}
+
+fn hex(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> String {
+    let bytes = (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+        .collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+// V0-VF, I, PC, SP, DT and ST, I and PC little endian
+fn registers(reply: &str) -> (Vec<u8>, u16, u16) {
+    let bytes: Vec<u8> = (0..reply.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&reply[index..index + 2], 16).unwrap())
+        .collect();
+    assert_eq!(bytes.len(), 16 + 2 + 2 + 3);
+    let word = |index: usize| u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+    (bytes[..16].to_vec(), word(16), word(18))
+}
+
+#[test]
+fn breakpoint_step_and_memory() {
+    let (mut client, server) = start(&PROGRAM);
+    assert_eq!(client.request("?"), "S05");
+    let (_, _, pc) = registers(&client.request("g"));
+    assert_eq!(pc, 0x200);
+
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    let (v, i, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], i, pc), (0x05, 0x300, 0x204));
+
+    // Continuing from a breakpoint runs its instruction and stops there again
+    assert_eq!(client.request("c"), "S05");
+    let (v, _, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], pc), (0x06, 0x204));
+
+    assert_eq!(client.request("z0,204,2"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    let (v, _, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], pc), (0x07, 0x206));
+
+    assert_eq!(client.request("m200,4"), "6005a300");
+    assert_eq!(client.request("M300,3:abcdef"), "OK");
+    assert_eq!(client.request("m300,3"), "abcdef");
+
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn invalid_packets_get_errors() {
+    let (mut client, server) = start(&PROGRAM);
+    assert_eq!(client.request("mffffffffffffffff,1"), "E01");
+    assert_eq!(client.request("m1,ffffffffffffffff"), "E01");
+    assert_eq!(client.request("mfff,2"), "E01");
+    assert_eq!(client.request("Mffffffffffffffff,1:00"), "E01");
+    assert_eq!(client.request("M300,2:00"), "E01");
+    assert_eq!(client.request("P11=ffff"), "E01");
+    let (_, _, pc) = registers(&client.request("g"));
+    assert_eq!(pc, 0x200);
+
+    // The server is still alive and steps normally
+    assert_eq!(client.request("s"), "S05");
+    let (v, _, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], pc), (0x05, 0x202));
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn faults_stop_with_sigill() {
+    // 200: RET with an empty stack
+    let (mut client, server) = start(&[0x00, 0xEE]);
+    assert_eq!(client.request("s"), "S04");
+    assert_eq!(client.request("?"), "S04");
+    // The PC stays on the faulting instruction and the VM stays halted
+    let (_, _, pc) = registers(&client.request("g"));
+    assert_eq!(pc, 0x200);
+    assert_eq!(client.request("c"), "S04");
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+
+    // 200: LD V0, 01  202: unknown instruction
+    let (mut client, server) = start(&[0x60, 0x01, 0xFF, 0xFF]);
+    assert_eq!(client.request("c"), "S04");
+    let (v, _, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], pc), (0x01, 0x202));
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+
+    // 200: CALL 200, recursing until the stack is full
+    let (mut client, server) = start(&[0x22, 0x00]);
+    assert_eq!(client.request("c"), "S04");
+    let (_, _, pc) = registers(&client.request("g"));
+    assert_eq!(pc, 0x200);
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn key_waits_stop_and_monitor_key_taps() {
+    // 200: LD V0, K
+    // 202: JP 202
+    let (mut client, server) = start(&[0xF0, 0x0A, 0x12, 0x02]);
+    assert_eq!(client.request("c"), "S05");
+    assert!(client.console.contains("monitor key"));
+    let (_, _, pc) = registers(&client.request("g"));
+    assert_eq!(pc, 0x202);
+
+    assert_eq!(
+        client.request(&format!("qRcmd,{}", hex("key g"))),
+        hex("Commands: key <0-F>, taps a keypad key for FX0A\n")
+    );
+    assert_eq!(client.request(&format!("qRcmd,{}", hex("key 5"))), "OK");
+    assert_eq!(client.request("Z0,202,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    let (v, _, pc) = registers(&client.request("g"));
+    assert_eq!((v[0], pc), (0x05, 0x202));
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+}
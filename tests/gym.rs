@@ -0,0 +1,49 @@
+// Runs roms/pong.ch8 through the gym style environment
+
+use std::path::PathBuf;
+
+use chip8::{
+    database::RomDatabase,
+    gym::{Environment, EnvironmentOptions, Game},
+    rom::{Rom, RomSource},
+};
+
+fn pong() -> Environment {
+    let rom = Rom::read(&RomSource::File(PathBuf::from("roms/pong.ch8"))).unwrap();
+    let options = EnvironmentOptions {
+        seed: 7,
+        ..EnvironmentOptions::default()
+    };
+    Environment::new(&rom, &RomDatabase::bundled(), options, Game::pong()).unwrap()
+}
+
+// Up, down or nothing with the left paddle, varied enough to move the ball
+fn action(step: u32) -> u16 {
+    [1 << 0x1, 1 << 0x4, 0][(step * 7 % 11 % 3) as usize]
+}
+
+#[test]
+fn clones_replay_the_same_steps() {
+    let mut environment = pong();
+    for step in 0..50 {
+        environment.step(action(step));
+    }
+    let mut clone = environment.clone();
+    for step in 50..500 {
+        let expected = environment.step(action(step));
+        assert_eq!(clone.step(action(step)), expected, "step {}", step);
+        if expected.2 {
+            assert_eq!(clone.reset(), environment.reset());
+        }
+    }
+    assert_eq!(clone.vm().memory(), environment.vm().memory());
+}
+
+#[test]
+fn game_addresses_must_be_in_memory() {
+    assert!(Game::score(0xFFF).is_ok());
+    assert!(Game::score(0x1000).is_err());
+    let game = Game::score(0x2F3).unwrap();
+    assert!(game.clone().done_when(0xFFF, 9).is_ok());
+    assert!(game.done_when(0x1000, 9).is_err());
+}